@@ -0,0 +1,50 @@
+use crate::Document;
+use crate::Position;
+use crate::Query;
+
+/// A document that isn't being shown, with its cursor and search state.
+pub struct Buffer {
+    pub document: Document,
+    pub cursor_position: Position,
+    pub offset: Position,
+    pub selection_anchor: Option<Position>,
+    pub search_query: Option<Query>,
+    pub search_match: Option<(Position, Position)>,
+}
+
+impl Buffer {
+    pub fn new(document: Document) -> Self {
+        Self {
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            selection_anchor: None,
+            search_query: None,
+            search_match: None,
+        }
+    }
+
+    /// Moves the cursor and selection back into the text after edits made
+    /// while the buffer wasn't shown.
+    pub fn clamp(&mut self) {
+        self.cursor_position = self.document.clamp(&self.cursor_position);
+        self.selection_anchor = self
+            .selection_anchor
+            .as_ref()
+            .map(|anchor| self.document.clamp(anchor));
+        self.search_match = None;
+        self.offset.y = self.offset.y.min(self.cursor_position.y);
+    }
+
+    /// Returns the name of `document` for lists, marked if it is unsaved.
+    pub fn name(document: &Document) -> String {
+        let name = document
+            .get_file_name()
+            .unwrap_or_else(|| "[No Name]".to_string());
+        if document.is_dirty() {
+            format!("{name} *")
+        } else {
+            name
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::storage;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DEFAULT_TAB_WIDTH: usize = 4;
+const USAGE: &str = "\
+Usage: lekh [OPTIONS] [+LINE[:COL]] [FILE[:LINE[:COL]]]...
+
+Options:
+  -h, --help             Print this help and exit
+  -V, --version          Print the version and exit
+  -R, --readonly         Open the files without allowing changes
+      --theme NAME       Highlight with the color theme NAME
+      --syntax NAME      Highlight as language NAME instead of detecting it
+      --tab-width N      Insert N spaces for Tab (default 4)
+      --tab-line         Show a tab for every open buffer above the panes
+      --mouse            Switch buffers by clicking their tabs
+      --build COMMAND    Run COMMAND on Ctrl-K and list the locations of the
+                         errors it reports
+  -q, --quickfix FILE    List the error locations in the compiler output in
+                         FILE, or in the standard input if FILE is -
+      --config FILE      Read settings from FILE instead of the default
+                         config file
+
+A file name starting with - or + has to come after --, as in
+`lekh -- +notes.txt`.
+
+Settings in the config file are `key = value` lines, with the keys
+`theme`, `tab_width`, `readonly`, `tab_line`, `mouse` and `build`.
+Options on the command line win.";
+
+/// A line and maybe a column, both counted from one, as typed by the user.
+pub type Location = (usize, Option<usize>);
+
+/// Everything the command line and the config file ask for.
+pub struct Args {
+    pub files: Vec<(String, Option<Location>)>,
+    pub read_only: bool,
+    pub theme: Option<String>,
+    pub syntax: Option<String>,
+    pub tab_width: usize,
+    pub tab_line: bool,
+    pub mouse: bool,
+    /// The command that builds the project, run on Ctrl-K.
+    pub build: Option<String>,
+    /// The file to read compiler output from, `-` for the standard input.
+    pub quickfix: Option<String>,
+}
+
+impl Args {
+    /// Parses the command line and the config file. Prints the help or the
+    /// version and exits if asked to, and exits with an error message if
+    /// anything can't be understood.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        match Self::try_parse(args) {
+            Ok(args) => args,
+            Err(message) => {
+                eprintln!("lekh: {message}");
+                eprintln!("Try 'lekh --help' for more information.");
+                process::exit(2);
+            }
+        }
+    }
+
+    fn try_parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut files = Vec::new();
+        let mut pending_location = None;
+        let mut read_only = None;
+        let mut tab_line = None;
+        let mut mouse = None;
+        let mut theme = None;
+        let mut syntax = None;
+        let mut tab_width = None;
+        let mut build = None;
+        let mut quickfix = None;
+        let mut config = None;
+
+        let mut args = args.into_iter();
+        let mut only_files = false;
+        while let Some(arg) = args.next() {
+            if only_files || !arg.starts_with(['-', '+']) || arg == "-" {
+                let (file, location) = split_location(&arg);
+                files.push((file.to_string(), location.or(pending_location.take())));
+                continue;
+            }
+
+            // Options can also be given as `--name=value`.
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, inline_value)) if name.starts_with("--") => {
+                    (name, Some(inline_value.to_string()))
+                }
+                _ => (&arg[..], None),
+            };
+            let mut value = |name: &str| match inline_value.clone() {
+                Some(value) => Ok(value),
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("option '{name}' requires a value")),
+            };
+
+            match name {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("lekh {VERSION}");
+                    process::exit(0);
+                }
+                "-R" | "--readonly" => read_only = Some(true),
+                "--tab-line" => tab_line = Some(true),
+                "--mouse" => mouse = Some(true),
+                "--theme" => theme = Some(value(name)?),
+                "--syntax" => syntax = Some(value(name)?),
+                "--tab-width" => tab_width = Some(parse_tab_width(&value(name)?)?),
+                "--build" => build = Some(value(name)?),
+                "-q" | "--quickfix" => quickfix = Some(value(name)?),
+                "--config" => config = Some(PathBuf::from(value(name)?)),
+                "--" => only_files = true,
+                _ => {
+                    let location = arg.strip_prefix('+').and_then(parse_line_column);
+                    if location.is_none() {
+                        return Err(format!(
+                            "unknown option '{arg}' (put -- before file names starting with - or +)"
+                        ));
+                    }
+                    pending_location = location;
+                }
+            }
+        }
+
+        // A location given after the last file belongs to it.
+        if let (Some(location), Some(last)) = (pending_location, files.last_mut()) {
+            last.1.get_or_insert(location);
+        }
+
+        let settings = match config {
+            Some(path) => Settings::load(&path)?,
+            None => match storage::config_dir().map(|dir| dir.join("config")) {
+                Some(path) if path.exists() => Settings::load(&path)?,
+                _ => Settings::default(),
+            },
+        };
+
+        Ok(Self {
+            files,
+            read_only: read_only.or(settings.read_only).unwrap_or(false),
+            tab_line: tab_line.or(settings.tab_line).unwrap_or(false),
+            mouse: mouse.or(settings.mouse).unwrap_or(false),
+            theme: theme.or(settings.theme),
+            syntax,
+            tab_width: tab_width
+                .or(settings.tab_width)
+                .unwrap_or(DEFAULT_TAB_WIDTH),
+            build: build.or(settings.build),
+            quickfix,
+        })
+    }
+}
+
+/// The settings read from a config file.
+#[derive(Default)]
+struct Settings {
+    read_only: Option<bool>,
+    tab_line: Option<bool>,
+    mouse: Option<bool>,
+    theme: Option<String>,
+    tab_width: Option<usize>,
+    build: Option<String>,
+}
+
+impl Settings {
+    fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("can't read config file '{}': {err}", path.display()))?;
+
+        let mut settings = Self::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| format!("{}:{}: {message}", path.display(), number + 1);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `key = value`".to_string()))?;
+            let value = value.trim();
+            let flag = || {
+                value
+                    .parse()
+                    .map_err(|_| error(format!("expected true or false, got '{value}'")))
+            };
+
+            match key.trim() {
+                "theme" => settings.theme = Some(value.to_string()),
+                "tab_width" => settings.tab_width = Some(parse_tab_width(value).map_err(error)?),
+                "readonly" => settings.read_only = Some(flag()?),
+                "tab_line" => settings.tab_line = Some(flag()?),
+                "mouse" => settings.mouse = Some(flag()?),
+                "build" => settings.build = Some(value.to_string()),
+                key => return Err(error(format!("unknown setting '{key}'"))),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+fn parse_tab_width(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(width) if (1..=16).contains(&width) => Ok(width),
+        _ => Err(format!(
+            "invalid tab width '{value}', expected a number from 1 to 16"
+        )),
+    }
+}
+
+/// Parses `line` or `line:column`.
+pub fn parse_line_column(text: &str) -> Option<Location> {
+    match text.split_once(':') {
+        Some((line, column)) => Some((line.parse().ok()?, Some(column.parse().ok()?))),
+        None => Some((text.parse().ok()?, None)),
+    }
+}
+
+/// Splits a `file:line` or `file:line:column` argument, the form compilers
+/// and grep print, into the file and the location in it. A file that exists
+/// is preferred, so names that end like a location still open as they are.
+fn split_location(arg: &str) -> (&str, Option<Location>) {
+    let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+    let mut candidates = vec![(arg, None)];
+    if let Some((rest, last)) = trimmed.rsplit_once(':') {
+        if let Some((file, line)) = rest.rsplit_once(':') {
+            if let (Ok(line), Ok(column)) = (line.parse(), last.parse()) {
+                candidates.push((file, Some((line, Some(column)))));
+            }
+        }
+        if let Ok(line) = last.parse() {
+            candidates.push((rest, Some((line, None))));
+        }
+    }
+    candidates.retain(|(file, _)| !file.is_empty());
+
+    candidates
+        .iter()
+        .find(|(file, _)| Path::new(file).exists())
+        .or_else(|| candidates.get(1))
+        .copied()
+        .unwrap_or((arg, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn parses_lines_and_columns() {
+        assert_eq!(parse_line_column("12"), Some((12, None)));
+        assert_eq!(parse_line_column("12:5"), Some((12, Some(5))));
+        for text in ["", "x", "12:", ":5", "12:x", "-3"] {
+            assert_eq!(parse_line_column(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn splits_locations_off_file_names() {
+        assert_eq!(
+            split_location("src/none.rs:10:4"),
+            ("src/none.rs", Some((10, Some(4))))
+        );
+        assert_eq!(split_location("none.txt:7"), ("none.txt", Some((7, None))));
+        assert_eq!(split_location("none.txt:7:"), ("none.txt", Some((7, None))));
+        assert_eq!(split_location("none.txt"), ("none.txt", None));
+        assert_eq!(split_location("none.txt:x"), ("none.txt:x", None));
+        assert_eq!(split_location(":5"), (":5", None));
+    }
+
+    #[test]
+    fn keeps_existing_files_whose_names_look_like_locations() {
+        let dir = env::temp_dir().join(format!("lekh-cli-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes:3");
+        fs::write(&file, "").unwrap();
+        let name = file.to_string_lossy().into_owned();
+
+        assert_eq!(split_location(&name), (&name[..], None));
+        let with_line = format!("{name}:2");
+        assert_eq!(split_location(&with_line), (&name[..], Some((2, None))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn parse(args: &[&str]) -> Result<Vec<(String, Option<Location>)>, String> {
+        let config = env::temp_dir().join(format!("lekh-cli-config-{}", process::id()));
+        fs::write(&config, "").unwrap();
+        let mut all = vec![
+            "--config".to_string(),
+            config.to_string_lossy().into_owned(),
+        ];
+        all.extend(args.iter().map(ToString::to_string));
+        Args::try_parse(all).map(|args| args.files)
+    }
+
+    #[test]
+    fn applies_plus_locations_to_files() {
+        assert_eq!(
+            parse(&["+3", "a.txt", "b.txt:4:2"]),
+            Ok(vec![
+                ("a.txt".to_string(), Some((3, None))),
+                ("b.txt".to_string(), Some((4, Some(2)))),
+            ])
+        );
+        // A location after the last file belongs to it.
+        assert_eq!(
+            parse(&["a.txt", "+3:2"]),
+            Ok(vec![("a.txt".to_string(), Some((3, Some(2))))])
+        );
+    }
+
+    #[test]
+    fn needs_a_double_dash_before_names_starting_with_plus() {
+        let error = parse(&["+notes.txt"]).unwrap_err();
+        assert!(error.contains("--"), "{error}");
+        assert_eq!(
+            parse(&["--", "+notes.txt", "-R"]),
+            Ok(vec![
+                ("+notes.txt".to_string(), None),
+                ("-R".to_string(), None),
+            ])
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::Terminal;
+
+/// Where copied text goes and where pasted text comes from.
+pub enum Backend {
+    /// Helper programs such as `wl-copy`/`wl-paste`, `xclip` or `xsel`.
+    Command {
+        copy: Vec<&'static str>,
+        paste: Vec<&'static str>,
+    },
+    /// OSC 52 escape sequences, which the terminal turns into a copy to the
+    /// system clipboard, even over SSH. Terminals rarely allow reading the
+    /// clipboard back, so pasting uses the text copied last.
+    Osc52,
+    /// A plain file, so copy and paste can be tested without a display
+    /// server by pointing `LEKH_CLIPBOARD` at `file:<path>`.
+    File(PathBuf),
+    /// Only the text copied from within the editor.
+    Internal,
+}
+
+impl Backend {
+    /// Picks a backend from `LEKH_CLIPBOARD` if it is set, otherwise from
+    /// the helper programs that are installed for the current session.
+    pub fn detect() -> Self {
+        if let Ok(name) = env::var("LEKH_CLIPBOARD") {
+            if let Some(backend) = Self::from_name(&name) {
+                return backend;
+            }
+        }
+
+        let remote = env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some();
+        if !remote {
+            if env::var_os("WAYLAND_DISPLAY").is_some() && is_installed("wl-copy") {
+                return Self::wayland();
+            }
+            if env::var_os("DISPLAY").is_some() {
+                if is_installed("xclip") {
+                    return Self::xclip();
+                }
+                if is_installed("xsel") {
+                    return Self::xsel();
+                }
+            }
+        }
+
+        Self::Osc52
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wayland" => Some(Self::wayland()),
+            "xclip" => Some(Self::xclip()),
+            "xsel" => Some(Self::xsel()),
+            "osc52" => Some(Self::Osc52),
+            "internal" => Some(Self::Internal),
+            _ => name
+                .strip_prefix("file:")
+                .map(|path| Self::File(PathBuf::from(path))),
+        }
+    }
+
+    fn wayland() -> Self {
+        Self::Command {
+            copy: vec!["wl-copy"],
+            paste: vec!["wl-paste", "--no-newline"],
+        }
+    }
+
+    fn xclip() -> Self {
+        Self::Command {
+            copy: vec!["xclip", "-selection", "clipboard", "-in"],
+            paste: vec!["xclip", "-selection", "clipboard", "-out"],
+        }
+    }
+
+    fn xsel() -> Self {
+        Self::Command {
+            copy: vec!["xsel", "--clipboard", "--input"],
+            paste: vec!["xsel", "--clipboard", "--output"],
+        }
+    }
+}
+
+pub struct Clipboard {
+    backend: Backend,
+    contents: String,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::with_backend(Backend::detect())
+    }
+}
+
+impl Clipboard {
+    pub fn with_backend(backend: Backend) -> Self {
+        Self {
+            backend,
+            contents: String::new(),
+        }
+    }
+
+    /// Copies `text`. The text is always kept in-process as well, so it can
+    /// still be pasted if the backend fails.
+    pub fn copy(&mut self, text: &str, terminal: &mut Terminal) -> Result<(), io::Error> {
+        if let Backend::Osc52 = self.backend {
+            self.contents = text.to_string();
+            return terminal.write_raw(&osc52(text, env::var_os("TMUX").is_some()));
+        }
+        self.copy_to_backend(text)
+    }
+
+    fn copy_to_backend(&mut self, text: &str) -> Result<(), io::Error> {
+        self.contents = text.to_string();
+
+        match &self.backend {
+            Backend::Command { copy, .. } => {
+                let mut child = Command::new(copy[0])
+                    .args(&copy[1..])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(text.as_bytes())?;
+                }
+                let status = child.wait()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(format!("{} failed: {status}", copy[0])))
+                }
+            }
+            Backend::File(path) => fs::write(path, text),
+            Backend::Osc52 | Backend::Internal => Ok(()),
+        }
+    }
+
+    /// Returns the text to paste, falling back to the text copied last if
+    /// the backend can't provide any.
+    pub fn paste(&self) -> String {
+        let pasted = match &self.backend {
+            Backend::Command { paste, .. } => Command::new(paste[0])
+                .args(&paste[1..])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok()),
+            Backend::File(path) => fs::read_to_string(path).ok(),
+            Backend::Osc52 | Backend::Internal => None,
+        };
+
+        pasted.unwrap_or_else(|| self.contents.clone())
+    }
+}
+
+/// Builds the escape sequence that asks the terminal to put `text` on the
+/// system clipboard, wrapped to pass through `tmux` if it is running.
+fn osc52(text: &str, tmux: bool) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64::encode(text));
+
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+fn is_installed(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_clipboard(name: &str) -> (Clipboard, PathBuf) {
+        let path = env::temp_dir().join(format!("lekh-clipboard-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        (Clipboard::with_backend(Backend::File(path.clone())), path)
+    }
+
+    #[test]
+    fn file_backend_round_trips() {
+        let (mut clipboard, path) = file_clipboard("round-trip");
+        clipboard.copy_to_backend("copied").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "copied");
+        assert_eq!(clipboard.paste(), "copied");
+
+        // Text put in the file from outside is what gets pasted.
+        fs::write(&path, "from outside").unwrap();
+        assert_eq!(clipboard.paste(), "from outside");
+
+        // Once the file is gone, the text copied last is pasted.
+        fs::remove_file(&path).unwrap();
+        assert_eq!(clipboard.paste(), "copied");
+    }
+
+    #[test]
+    fn file_backend_keeps_several_lines_as_they_are() {
+        let (mut clipboard, path) = file_clipboard("lines");
+        clipboard.copy_to_backend("rst\nsec\r\n\u{e9}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), "rst\nsec\r\n\u{e9}".as_bytes());
+        assert_eq!(clipboard.paste(), "rst\nsec\r\n\u{e9}");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn picks_a_backend_by_name() {
+        let path = |backend: Option<Backend>| match backend {
+            Some(Backend::File(path)) => Some(path),
+            _ => None,
+        };
+        assert_eq!(
+            path(Backend::from_name("file:/tmp/clip board")),
+            Some(PathBuf::from("/tmp/clip board"))
+        );
+        assert!(matches!(Backend::from_name("osc52"), Some(Backend::Osc52)));
+        assert!(matches!(
+            Backend::from_name("xclip"),
+            Some(Backend::Command { copy, .. }) if copy[0] == "xclip"
+        ));
+        assert!(Backend::from_name("clipboard").is_none());
+    }
+
+    #[test]
+    fn encodes_osc52_sequences() {
+        assert_eq!(osc52("hi", false), "\x1b]52;c;aGk=\x07");
+        assert_eq!(osc52("", false), "\x1b]52;c;\x07");
+        assert_eq!(osc52("caf\u{e9}\n", false), "\x1b]52;c;Y2Fmw6kK\x07");
+        assert_eq!(osc52("hi", true), "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\");
+    }
+
+    #[test]
+    fn reports_a_failed_copy_command() {
+        let mut clipboard = Clipboard::with_backend(Backend::Command {
+            copy: vec!["false"],
+            paste: vec!["false"],
+        });
+        assert!(clipboard.copy_to_backend("text").is_err());
+        assert_eq!(clipboard.paste(), "text");
+    }
+}
@@ -1,203 +1,1026 @@
+use std::cell::{RefCell, RefMut};
 use std::fs;
-use std::io::Write;
-use std::process;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
 
+use ropey::Rope;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::storage;
+use crate::Edit;
 use crate::Highlighter;
+use crate::History;
 use crate::Position;
+use crate::Query;
 use crate::Row;
 use crate::SearchDirection;
 use crate::Terminal;
 
+/// How many rows past the rows searched by `find_all` a match can run on.
+const MAX_MATCH_ROWS: usize = 100;
+
+/// The text as one string, for the searches that need it, and the start
+/// of every match of the last query counted in it. Both are made again
+/// only after the text changed.
+#[derive(Default)]
+struct Snapshot {
+    version: Option<usize>,
+    text: String,
+    matches: Option<(Query, Vec<usize>)>,
+}
+
 pub struct Document {
-    rows: Vec<Row>,
+    text: Rope,
+    /// Counts the edits applied to the text.
+    version: usize,
+    snapshot: RefCell<Snapshot>,
     file_name: Option<String>,
-    dirty: bool,
+    history: History,
+    saved_revision: usize,
+    read_only: bool,
+    /// Char indices that move along with the text around them as it is
+    /// edited.
+    marks: Vec<usize>,
     pub highlighter: Highlighter,
 }
 
-impl Document {
-    pub fn default() -> Self {
+impl Default for Document {
+    fn default() -> Self {
         let highlighter = Highlighter::default();
         Self {
-            rows: vec![],
+            text: Rope::new(),
+            version: 0,
+            snapshot: RefCell::default(),
             file_name: None,
-            dirty: false,
+            history: History::default(),
+            saved_revision: 0,
+            read_only: false,
+            marks: Vec::new(),
             highlighter,
         }
     }
+}
 
+impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
         let contents = fs::read_to_string(filename)?;
+        let content_hash = storage::hash([contents.as_bytes()]);
+        let mut text = Rope::from_str(&contents);
+
+        // Every row is terminated by a newline, including the last one.
+        let len_chars = text.len_chars();
+        if len_chars > 0 && text.char(len_chars - 1) != '\n' {
+            text.insert_char(len_chars, '\n');
+        }
 
         let mut highlighter = Highlighter::default();
-        highlighter.set_file_name(filename.to_string());
+        highlighter.set_file_name(filename);
+        highlighter.reset(text.len_lines().saturating_sub(1));
 
-        let rows: Vec<Row> = if let Ok(rows) = highlighter.highlight_contents(&contents[..]) {
-            rows
-        } else {
-            eprintln!("Error: Couldn't highlight the file.\r");
-            process::exit(103)
-        };
+        let history = History::restore(Path::new(filename), content_hash).unwrap_or_default();
+        let saved_revision = history.revision();
 
         Ok(Self {
-            rows,
+            text,
+            version: 0,
+            snapshot: RefCell::default(),
             file_name: Some(filename.to_string()),
-            dirty: false,
+            history,
+            saved_revision,
+            read_only: false,
+            marks: Vec::new(),
             highlighter,
         })
     }
 
     pub fn get_file_name(&self) -> Option<String> {
-        if let Some(filename) = &self.file_name {
-            Some(filename.clone())
-        } else {
-            None
-        }
+        self.file_name.clone()
     }
 
     pub fn set_file_name(&mut self, file_name: String) {
+        self.highlighter.set_file_name(&file_name);
         self.file_name = Some(file_name);
     }
 
-    pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Sets the positions to keep track of, replacing any set before. As the
+    /// text is edited, each moves along with the text after it.
+    pub fn set_marks(&mut self, positions: &[Position]) {
+        self.marks = positions
+            .iter()
+            .map(|position| self.char_index(position))
+            .collect();
+    }
+
+    /// Returns where the mark set at `index` is now.
+    pub fn mark(&self, index: usize) -> Option<Position> {
+        let mark = *self.marks.get(index)?;
+        Some(self.position_of(mark.min(self.text.len_chars())))
+    }
+
+    pub fn row(&self, index: usize) -> Option<Row> {
+        let line = self.line(index)?;
+        let highlighted = self.highlighter.line(index).unwrap_or(&line[..]);
+        Some(Row::from(&line, highlighted))
     }
 
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 0
     }
 
     pub fn len(&self) -> usize {
-        self.rows.len()
+        self.text.len_lines().saturating_sub(1)
+    }
+
+    /// Returns the whole text.
+    pub fn contents(&self) -> String {
+        self.text.to_string()
+    }
+
+    /// Returns `at` moved back into the text if it is past the end of its
+    /// row or of the document.
+    pub fn clamp(&self, at: &Position) -> Position {
+        let y = at.y.min(self.len());
+        Position {
+            x: at.x.min(self.row_len(y)),
+            y,
+        }
+    }
+
+    /// Returns the contents of the row at `index` without its trailing newline.
+    pub fn line(&self, index: usize) -> Option<String> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut line = self.text.line(index).to_string();
+        line.pop();
+        Some(line)
     }
 
-    pub fn highlight(&mut self) {
-        if let Some(filename) = &self.file_name {
-            self.highlighter.set_file_name(filename.to_string());
+    /// Brings the highlighting of every row before `until` up to date.
+    pub fn highlight(&mut self, until: usize) {
+        if self.highlighter.update(&self.text, until).is_err() {
+            Terminal::cleanup_and_exit(Some("Error: Couldn't highlight the file."), 103);
         }
-        let highlighter = &self.highlighter;
+    }
+
+    fn row_len(&self, index: usize) -> usize {
+        self.line(index)
+            .map_or(0, |line| line.graphemes(true).count())
+    }
 
-        let mut contents = String::new();
-        for row in &self.rows {
-            contents.push_str(row.get_string());
-            contents.push('\n');
+    /// Converts a grapheme position into a char index into the rope.
+    ///
+    /// Positions past the last row map to the end of the text.
+    fn char_index(&self, at: &Position) -> usize {
+        if at.y >= self.len() {
+            return self.text.len_chars();
         }
+        let line_start = self.text.line_to_char(at.y);
+        let line = self.text.line(at.y).to_string();
+
+        let offset = line[..line.len() - 1]
+            .graphemes(true)
+            .take(at.x)
+            .map(|grapheme| grapheme.chars().count())
+            .sum::<usize>();
+
+        line_start + offset
+    }
+
+    /// Returns the range of chars taken up by the grapheme at `at`.
+    fn grapheme_range(&self, at: &Position) -> Option<(usize, usize)> {
+        let line = self.line(at.y)?;
+        let grapheme = line.graphemes(true).nth(at.x)?;
+        let start = self.char_index(at);
+
+        Some((start, start + grapheme.chars().count()))
+    }
+
+    /// Converts a char index into the rope back into a grapheme position.
+    fn position_of(&self, index: usize) -> Position {
+        let y = self.text.char_to_line(index);
+        let line_start = self.text.line_to_char(y);
+        let x = self
+            .text
+            .slice(line_start..index)
+            .to_string()
+            .graphemes(true)
+            .count();
+
+        Position { x, y }
+    }
+
+    /// Applies an edit to the text and tells the highlighter which rows it
+    /// touched.
+    fn apply(&mut self, edit: &Edit) {
+        let y = self.text.char_to_line(edit.index);
+        let removed_chars = edit.removed.chars().count();
+        // The row the edit ends in is only touched if there is text after
+        // the edit; otherwise it is the empty row past the end of the text.
+        let touched = usize::from(edit.index + removed_chars < self.text.len_chars());
+        let old_lines = edit.removed.matches('\n').count() + touched;
+        let new_lines = edit.inserted.matches('\n').count() + touched;
+
+        self.text.remove(edit.index..edit.index + removed_chars);
+        self.text.insert(edit.index, &edit.inserted);
+        self.version += 1;
 
-        let rows: Vec<Row> = if let Ok(rows) = highlighter.highlight_contents(&contents[..]) {
-            rows
+        // Marks in the removed text end up where it was.
+        let inserted_chars = edit.inserted.chars().count();
+        for mark in &mut self.marks {
+            if *mark >= edit.index + removed_chars {
+                *mark = *mark - removed_chars + inserted_chars;
+            } else if *mark > edit.index {
+                *mark = edit.index;
+            }
+        }
+
+        self.highlighter.edit(y, old_lines, new_lines);
+    }
+
+    fn record(&mut self, edit: Edit) {
+        self.apply(&edit);
+        self.history.record(edit);
+    }
+
+    /// Builds the edit that inserts `text` at char `index`, keeping the
+    /// last row terminated by a newline.
+    fn insertion(&self, index: usize, text: &str) -> Edit {
+        if index == self.text.len_chars() && !text.ends_with('\n') {
+            Edit::insert(index, &format!("{text}\n"))
         } else {
-            Terminal::cleanup_and_exit(Some("Error: Couldn't highlight the file."), 103)
-        };
-        self.rows = rows;
+            Edit::insert(index, text)
+        }
+    }
+
+    /// Builds the edit that deletes the grapheme at `at`, or joins the next
+    /// row onto this one when `at` is at the end of the row.
+    fn deletion(&self, at: &Position) -> Option<Edit> {
+        let len = self.len();
+        if at.y >= len {
+            return None;
+        }
+
+        if at.x >= self.row_len(at.y) {
+            if at.y + 1 < len {
+                let newline = self.text.line_to_char(at.y + 1) - 1;
+                return Some(Edit::remove(newline, "\n"));
+            }
+            return None;
+        }
+
+        let (start, end) = self.grapheme_range(at)?;
+        let removed = self.text.slice(start..end).to_string();
+        Some(Edit::remove(start, &removed))
     }
 
     pub fn insert_newline(&mut self, at: &Position) {
         if at.y > self.len() {
             return;
         }
-        self.dirty = true;
 
-        if at.y == self.len() {
-            self.rows.push(Row::default());
+        let edit = self.insertion(self.char_index(at), "\n");
+        self.record(edit);
+    }
+
+    pub fn insert(&mut self, at: &Position, c: char) {
+        if at.y > self.len() {
             return;
         }
 
-        let new_row = self.rows.get_mut(at.y).unwrap().split(at.x);
-        self.rows.insert(at.y + 1, new_row);
+        let edit = self.insertion(self.char_index(at), &c.to_string());
+        self.record(edit);
+    }
 
-        self.highlight();
+    pub fn delete(&mut self, at: &Position) {
+        if let Some(edit) = self.deletion(at) {
+            self.record(edit);
+        }
     }
 
-    pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.len() {
-            return;
+    /// Applies an edit at every cursor as one undo step, then moves each
+    /// cursor to where its edit leaves it.
+    ///
+    /// The edits are made front to back, so every cursor is shifted by the
+    /// text inserted and removed before it, including on the same row.
+    /// `edit_at` gets the char index of a cursor and returns the edit to make
+    /// there along with the cursor's new char index relative to the edit.
+    fn edit_at_cursors<F>(&mut self, cursors: &mut [Position], edit_at: F)
+    where
+        F: Fn(&Self, usize) -> Option<(Edit, usize)>,
+    {
+        let mut order: Vec<(usize, usize)> = cursors
+            .iter()
+            .enumerate()
+            .map(|(cursor, position)| (self.char_index(position), cursor))
+            .collect();
+        order.sort_unstable();
+
+        let mut inserted = 0;
+        let mut removed = 0;
+        let mut previous: Option<(usize, usize)> = None;
+        let mut moved = vec![0; cursors.len()];
+
+        self.history.begin_group();
+        for (index, cursor) in order {
+            // Cursors on the same spot only get the edit once.
+            if let Some((previous_index, previous_moved)) = previous {
+                if previous_index == index {
+                    moved[cursor] = previous_moved;
+                    continue;
+                }
+            }
+
+            let shifted = (index + inserted).saturating_sub(removed);
+            moved[cursor] = if let Some((edit, offset)) = edit_at(self, shifted) {
+                inserted += edit.inserted.chars().count();
+                removed += edit.removed.chars().count();
+                let new_index = edit.index + offset;
+                self.record(edit);
+                new_index
+            } else {
+                shifted
+            };
+            previous = Some((index, moved[cursor]));
         }
-        self.dirty = true;
+        self.history.end_group();
 
-        if at.y == self.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
-        } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.insert(at.x, c);
+        // Later edits all start at or after the earlier cursors, so the
+        // indices are final.
+        for (position, index) in cursors.iter_mut().zip(moved) {
+            *position = self.position_of(index);
         }
+    }
 
-        self.highlight();
+    /// Inserts `text` at every cursor and moves the cursors past it.
+    pub fn insert_at_cursors(&mut self, cursors: &mut [Position], text: &str) {
+        let len = text.chars().count();
+        self.edit_at_cursors(cursors, |document, index| {
+            Some((document.insertion(index, text), len))
+        });
     }
 
-    pub fn delete(&mut self, at: &Position) {
-        let len = self.len();
-        if at.y >= len {
+    /// Deletes the grapheme under every cursor.
+    pub fn delete_at_cursors(&mut self, cursors: &mut [Position]) {
+        self.edit_at_cursors(cursors, |document, index| {
+            let edit = document.deletion(&document.position_of(index))?;
+            Some((edit, 0))
+        });
+    }
+
+    /// Deletes the grapheme before every cursor.
+    pub fn backspace_at_cursors(&mut self, cursors: &mut [Position]) {
+        self.edit_at_cursors(cursors, |document, index| {
+            let at = document.position_of(index);
+            let before = if at.x > 0 {
+                Position {
+                    x: at.x - 1,
+                    y: at.y,
+                }
+            } else if at.y > 0 && at.y < document.len() {
+                Position {
+                    x: document.row_len(at.y - 1),
+                    y: at.y - 1,
+                }
+            } else {
+                return None;
+            };
+            let edit = document.deletion(&before)?;
+            Some((edit, 0))
+        });
+    }
+
+    /// Returns the text between two positions, `start` included and `end`
+    /// excluded.
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let start = self.char_index(start);
+        let end = self.char_index(end).max(start);
+        self.text.slice(start..end).to_string()
+    }
+
+    /// Deletes the text between two positions as a single undo step.
+    pub fn delete_range(&mut self, start: &Position, end: &Position) {
+        let start = self.char_index(start);
+        let mut end = self.char_index(end);
+
+        // Keep the newline that terminates the last row unless the whole row
+        // goes away.
+        if end == self.text.len_chars() && start > 0 && self.text.char(start - 1) != '\n' {
+            end -= 1;
+        }
+        if end <= start {
             return;
         }
-        self.dirty = true;
 
-        if at.x == self.rows.get_mut(at.y).unwrap().len() && at.y < len - 1 {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.append(&next_row);
-        } else {
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.delete(at.x);
+        let removed = self.text.slice(start..end).to_string();
+        self.history.seal();
+        self.record(Edit::remove(start, &removed));
+        self.history.seal();
+    }
+
+    /// Inserts `text` as a single undo step and returns the position right
+    /// after it.
+    pub fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        if text.is_empty() || at.y > self.len() {
+            return at.clone();
+        }
+        let index = self.char_index(at);
+        let end = index + text.chars().count();
+
+        let mut inserted = text.to_string();
+        if index == self.text.len_chars() && !inserted.ends_with('\n') {
+            inserted.push('\n');
+        }
+        self.history.seal();
+        self.record(Edit::insert(index, &inserted));
+        self.history.seal();
+
+        self.position_of(end)
+    }
+
+    /// Replaces the text between two positions by `text` as a single undo
+    /// step and returns the position right after it.
+    pub fn replace_range(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        self.history.begin_group();
+        self.delete_range(start, end);
+        let end = self.insert_text(start, text);
+        self.history.end_group();
+        end
+    }
+
+    /// Reverts the last group of edits and returns where the cursor should go.
+    pub fn undo(&mut self) -> Option<Position> {
+        let edits = self.history.undo()?;
+        Some(self.apply_all(&edits))
+    }
+
+    /// Reapplies the last undone group of edits and returns where the cursor
+    /// should go.
+    pub fn redo(&mut self) -> Option<Position> {
+        let edits = self.history.redo()?;
+        Some(self.apply_all(&edits))
+    }
+
+    fn apply_all(&mut self, edits: &[Edit]) -> Position {
+        let mut cursor = 0;
+        for edit in edits {
+            self.apply(edit);
+            cursor = edit.index + edit.inserted.chars().count();
         }
+        self.position_of(cursor)
+    }
+
+    /// Starts a group of edits that is undone as a single step, until the
+    /// matching `end_edit_group`.
+    pub fn begin_edit_group(&mut self) {
+        self.history.begin_group();
+    }
 
-        self.highlight();
+    pub fn end_edit_group(&mut self) {
+        self.history.end_group();
+    }
+
+    /// Ends the current run of typing, so that the next edit gets undone
+    /// separately.
+    pub fn seal_history(&mut self) {
+        self.history.seal();
     }
 
     pub fn save(&mut self) -> Result<(), std::io::Error> {
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
-            }
-            self.dirty = false;
+            let file = fs::File::create(file_name)?;
+            let mut writer = BufWriter::new(file);
+            self.text.write_to(&mut writer)?;
+            writer.flush()?;
+
+            self.saved_revision = self.history.revision();
+            self.history.seal();
+
+            // Losing the undo history is not worth failing the save over.
+            let content_hash = storage::hash(self.text.chunks().map(str::as_bytes));
+            let _ = self.history.persist(Path::new(file_name), content_hash);
         }
         Ok(())
     }
 
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.history.revision() != self.saved_revision
     }
 
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
-        if at.y >= self.rows.len() {
-            return None;
-        }
+    /// Returns the start and end of the next match of `query` from `at` in
+    /// `direction`.
+    ///
+    /// The whole text is searched as one string, so a match can span rows.
+    pub fn find(
+        &self,
+        query: &Query,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<(Position, Position)> {
+        let at = self.text.char_to_byte(self.char_index(at));
+        let found = self.with_text(|text| {
+            if direction == SearchDirection::Forward {
+                query.find_after(text, at)
+            } else {
+                query.find_before(text, at)
+            }
+        })?;
+
+        Some((
+            self.position_of(self.text.byte_to_char(found.start)),
+            self.position_of(self.text.byte_to_char(found.end)),
+        ))
+    }
 
-        let mut position = Position { x: at.x, y: at.y };
+    /// Like `find`, but carries on from the other end of the text when there
+    /// is no match before the end in `direction`. The returned flag tells
+    /// whether the search wrapped around. `None` means the search went all
+    /// the way around without a match.
+    pub fn find_wrapping(
+        &self,
+        query: &Query,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<(Position, Position, bool)> {
+        if let Some((start, end)) = self.find(query, at, direction) {
+            return Some((start, end, false));
+        }
 
-        let start = if direction == SearchDirection::Forward {
-            at.y
+        let other_end = if direction == SearchDirection::Forward {
+            Position::default()
         } else {
-            0
+            Position {
+                x: 0,
+                y: self.len(),
+            }
         };
+        self.find(query, &other_end, direction)
+            .map(|(start, end)| (start, end, true))
+    }
 
-        let end = if direction == SearchDirection::Forward {
-            self.rows.len()
-        } else {
-            at.y.saturating_add(1)
+    /// Returns the start and end of every match of `query` that is at
+    /// least partly in one of the `rows`. Only those rows and the few around
+    /// them are searched.
+    pub fn find_all(&self, query: &Query, rows: Range<usize>) -> Vec<(Position, Position)> {
+        let len = self.len();
+        let first = rows.start.saturating_sub(MAX_MATCH_ROWS).min(len);
+        let last = rows.end.saturating_add(MAX_MATCH_ROWS).min(len);
+        let offset = self.text.line_to_byte(first);
+        let text = self
+            .text
+            .slice(self.text.line_to_char(first)..self.text.line_to_char(last))
+            .to_string();
+        let start = self.text.line_to_byte(rows.start.clamp(first, len)) - offset;
+        let end = self.text.line_to_byte(rows.end.clamp(first, len)) - offset;
+
+        query
+            .find_all(&text, 0..end)
+            .into_iter()
+            .filter(|found| found.end > start)
+            .map(|found| {
+                (
+                    self.position_of(self.text.byte_to_char(offset + found.start)),
+                    self.position_of(self.text.byte_to_char(offset + found.end)),
+                )
+            })
+            .collect()
+    }
+
+    /// Returns how many matches of `query` start at or before `at`, and how
+    /// many there are in all.
+    pub fn count_matches(&self, query: &Query, at: &Position) -> (usize, usize) {
+        let at = self.text.char_to_byte(self.char_index(at));
+        let mut snapshot = self.snapshot();
+        let Snapshot { text, matches, .. } = &mut *snapshot;
+        if matches.as_ref().is_none_or(|(counted, _)| counted != query) {
+            let starts = query
+                .find_all(text, 0..text.len())
+                .into_iter()
+                .map(|found| found.start)
+                .collect();
+            *matches = Some((query.clone(), starts));
+        }
+        let starts = matches.as_ref().map_or(&[][..], |(_, starts)| &starts[..]);
+        (starts.partition_point(|start| *start <= at), starts.len())
+    }
+
+    /// Calls `f` with the whole text as one string.
+    fn with_text<R>(&self, f: impl FnOnce(&str) -> R) -> R {
+        f(&self.snapshot().text)
+    }
+
+    fn snapshot(&self) -> RefMut<'_, Snapshot> {
+        let mut snapshot = self.snapshot.borrow_mut();
+        if snapshot.version != Some(self.version) {
+            *snapshot = Snapshot {
+                version: Some(self.version),
+                text: self.text.to_string(),
+                matches: None,
+            };
+        }
+        snapshot
+    }
+
+    /// Replaces the match of `query` from `start` to `end` by `template`,
+    /// with capture references expanded, and returns the position right
+    /// after the replacement.
+    pub fn replace(
+        &mut self,
+        query: &Query,
+        start: &Position,
+        end: &Position,
+        template: &str,
+    ) -> Position {
+        let index = self.char_index(start);
+        let range = self.text.char_to_byte(index)..self.text.char_to_byte(self.char_index(end));
+
+        let (edit, replacement_chars) = self.with_text(|text| {
+            let replacement = query.replacement(text, range.clone(), template);
+            let replacement_chars = replacement.chars().count();
+            (
+                self.replacement_edit(index, &text[range], replacement),
+                replacement_chars,
+            )
+        });
+        self.history.seal();
+        self.record(edit);
+        self.history.seal();
+
+        self.position_of(index + replacement_chars)
+    }
+
+    /// Replaces every match of `query` from `at` to the end of the text by
+    /// `template` as a single undo step. Returns how many matches there were
+    /// and the position right after the last replacement.
+    pub fn replace_all(
+        &mut self,
+        query: &Query,
+        at: &Position,
+        template: &str,
+    ) -> (usize, Position) {
+        let from = self.text.char_to_byte(self.char_index(at));
+        let Some((edits, end)) = self.with_text(|text| {
+            let found = query.find_all(text, from..text.len());
+            let mut end = self.text.byte_to_char(found.last()?.end);
+            // Going from the last match to the first, the char indices of
+            // the matches not replaced yet stay the same.
+            let edits: Vec<Edit> = found
+                .into_iter()
+                .rev()
+                .map(|range| {
+                    let replacement = query.replacement(text, range.clone(), template);
+                    let removed = &text[range.clone()];
+                    end =
+                        (end + replacement.chars().count()).saturating_sub(removed.chars().count());
+                    self.replacement_edit(self.text.byte_to_char(range.start), removed, replacement)
+                })
+                .collect();
+            Some((edits, end))
+        }) else {
+            return (0, at.clone());
         };
 
-        for _ in start..end {
-            if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(&query, position.x, direction) {
-                    position.x = x;
-                    return Some(position);
-                }
+        let count = edits.len();
+        self.history.begin_group();
+        for edit in edits {
+            self.record(edit);
+        }
+        self.history.end_group();
 
-                if direction == SearchDirection::Forward {
-                    position.y = position.y.saturating_add(1);
-                    position.x = 0;
-                } else {
-                    position.y = position.y.saturating_sub(1);
-                    position.x = self.rows[position.y].len();
-                }
-            } else {
-                return None;
-            }
+        (count, self.position_of(end.min(self.text.len_chars())))
+    }
+
+    /// Builds the edit that replaces `removed`, found at char `index`, by
+    /// `replacement`, keeping the last row terminated by a newline unless
+    /// the whole text goes away.
+    fn replacement_edit(&self, index: usize, removed: &str, replacement: String) -> Edit {
+        let mut inserted = replacement;
+        let removes_last_newline =
+            index + removed.chars().count() == self.text.len_chars() && !inserted.ends_with('\n');
+        let leaves_row_open = if index > 0 {
+            self.text.char(index - 1) != '\n'
+        } else {
+            !inserted.is_empty()
+        };
+        if removes_last_newline && leaves_row_open {
+            inserted.push('\n');
+        }
+
+        Edit {
+            index,
+            removed: removed.to_string(),
+            inserted,
         }
-        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchOptions;
+
+    fn document(text: &str) -> Document {
+        let mut document = Document::default();
+        document.insert_text(&Position::default(), text);
+        document
+    }
+
+    fn contents(document: &Document) -> String {
+        document.text.to_string()
+    }
+
+    fn span((start, end): (Position, Position)) -> (usize, usize, usize, usize) {
+        (start.x, start.y, end.x, end.y)
+    }
+
+    #[test]
+    fn searches_see_the_text_as_edited() {
+        let mut document = document("one\ntwo\n");
+        let query = Query::new("ne\ntw", SearchOptions::default()).unwrap();
+        let find = |document: &Document| {
+            document
+                .find(&query, &Position::default(), SearchDirection::Forward)
+                .map(span)
+        };
+        assert_eq!(find(&document), Some((1, 0, 2, 1)));
+
+        document.insert(&Position { x: 3, y: 0 }, '!');
+        assert_eq!(find(&document), None);
+
+        document.undo();
+        assert_eq!(find(&document), Some((1, 0, 2, 1)));
+    }
+
+    #[test]
+    fn finds_the_matches_starting_in_some_rows() {
+        let document = document("x1\ny2\nx3\ny4\nx5\n");
+        let query = Query::new("x", SearchOptions::default()).unwrap();
+        let found: Vec<_> = document
+            .find_all(&query, 1..4)
+            .into_iter()
+            .map(span)
+            .collect();
+        assert_eq!(found, [(0, 2, 1, 2)]);
+
+        // A match starting in the rows can end below them.
+        let query = Query::new("3\ny4\nx", SearchOptions::default()).unwrap();
+        let found: Vec<_> = document
+            .find_all(&query, 0..3)
+            .into_iter()
+            .map(span)
+            .collect();
+        assert_eq!(found, [(1, 2, 1, 4)]);
+    }
+
+    #[test]
+    fn finds_the_matches_running_into_some_rows() {
+        let document = document("x1\ny2\nx3\ny4\n");
+        let query = Query::new("2\nx3\ny", SearchOptions::default()).unwrap();
+        let found = |rows| -> Vec<_> {
+            document
+                .find_all(&query, rows)
+                .into_iter()
+                .map(span)
+                .collect()
+        };
+        assert_eq!(found(2..3), [(1, 1, 1, 3)]);
+        assert_eq!(found(3..4), [(1, 1, 1, 3)]);
+        assert_eq!(found(4..5), []);
+    }
+
+    #[test]
+    fn counts_the_matches_up_to_a_position() {
+        let mut document = document("ab ab\nab\n");
+        let query = Query::new("ab", SearchOptions::default()).unwrap();
+        assert_eq!(document.count_matches(&query, &Position::default()), (1, 3));
+        assert_eq!(
+            document.count_matches(&query, &Position { x: 3, y: 0 }),
+            (2, 3)
+        );
+        assert_eq!(
+            document.count_matches(&query, &Position { x: 1, y: 1 }),
+            (3, 3)
+        );
+
+        document.insert_text(&Position::default(), "ab");
+        assert_eq!(
+            document.count_matches(&query, &Position { x: 3, y: 0 }),
+            (2, 4)
+        );
+
+        let other = Query::new("b", SearchOptions::default()).unwrap();
+        assert_eq!(
+            document.count_matches(&other, &Position { x: 9, y: 0 }),
+            (3, 4)
+        );
+    }
+
+    #[test]
+    fn replaces_every_match_after_a_position_in_one_undo_step() {
+        let mut document = document("a1 b2\nc3 d4\ne5\n");
+        let options = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let query = Query::new(r"([a-z])(\d)", options).unwrap();
+
+        let (count, end) = document.replace_all(&query, &Position { x: 3, y: 0 }, "$2$1");
+        assert_eq!(count, 4);
+        assert_eq!(contents(&document), "a1 2b\n3c 4d\n5e\n");
+        assert_eq!((end.x, end.y), (2, 2));
+
+        document.undo();
+        assert_eq!(contents(&document), "a1 b2\nc3 d4\ne5\n");
+    }
+
+    #[test]
+    fn replacing_the_whole_text_keeps_the_last_newline() {
+        let mut document = document("abc\n");
+        let query = Query::new("abc\n", SearchOptions::default()).unwrap();
+        let (count, _) = document.replace_all(&query, &Position::default(), "x");
+        assert_eq!(count, 1);
+        assert_eq!(contents(&document), "x\n");
+        assert_eq!(document.highlighter.line_count(), document.len());
+    }
+
+    #[test]
+    fn highlighter_keeps_one_line_per_row_at_end_of_text() {
+        let mut document = document("a\nb\n");
+        assert_eq!(document.highlighter.line_count(), 2);
+
+        document.delete_range(&Position { x: 0, y: 1 }, &Position { x: 0, y: 2 });
+        assert_eq!(document.len(), 1);
+        assert_eq!(document.highlighter.line_count(), document.len());
+
+        document.undo();
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.highlighter.line_count(), document.len());
+
+        document.redo();
+        assert_eq!(document.highlighter.line_count(), document.len());
+
+        document.insert(&Position { x: 0, y: 1 }, 'c');
+        document.insert_newline(&Position { x: 1, y: 1 });
+        assert_eq!(document.len(), 3);
+        assert_eq!(document.highlighter.line_count(), document.len());
+
+        document.delete(&Position { x: 1, y: 0 });
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.highlighter.line_count(), document.len());
+    }
+
+    #[test]
+    fn highlights_rows_added_at_the_end_like_a_fresh_highlight() {
+        let highlighted = |document: &mut Document| -> Vec<String> {
+            document.highlight(document.len());
+            (0..document.len())
+                .map(|y| document.highlighter.line(y).unwrap_or_default().to_string())
+                .collect()
+        };
+        let fresh = |document: &Document| {
+            let mut fresh = Document::default();
+            fresh.set_file_name("test.rs".to_string());
+            fresh.insert_text(&Position::default(), &contents(document));
+            highlighted(&mut fresh)
+        };
+
+        let mut document = Document::default();
+        document.set_file_name("test.rs".to_string());
+        document.insert_text(&Position::default(), "/* open\n");
+        highlighted(&mut document);
+
+        document.insert(&Position { x: 0, y: 1 }, 'x');
+        assert_eq!(highlighted(&mut document), fresh(&document));
+
+        document.insert_text(&Position { x: 0, y: 2 }, "y\nz */\nfn f() {}\n");
+        assert_eq!(highlighted(&mut document), fresh(&document));
+
+        document.delete_range(&Position { x: 0, y: 3 }, &Position { x: 0, y: 5 });
+        assert_eq!(highlighted(&mut document), fresh(&document));
+
+        document.undo();
+        assert_eq!(highlighted(&mut document), fresh(&document));
+
+        document.redo();
+        document.insert_newline(&Position { x: 1, y: 2 });
+        assert_eq!(highlighted(&mut document), fresh(&document));
+    }
+
+    #[test]
+    fn pastes_over_a_selection_in_one_undo_step() {
+        let mut document = document("one two three\n");
+        let copied = document.text_range(&Position { x: 4, y: 0 }, &Position { x: 7, y: 0 });
+
+        let end =
+            document.replace_range(&Position { x: 8, y: 0 }, &Position { x: 13, y: 0 }, &copied);
+        assert_eq!(contents(&document), "one two two\n");
+        assert_eq!((end.x, end.y), (11, 0));
+
+        document.undo();
+        assert_eq!(contents(&document), "one two three\n");
+    }
+
+    #[test]
+    fn pastes_multiple_lines() {
+        let mut document = document("first\nsecond\nthird\n");
+        let copied = document.text_range(&Position { x: 2, y: 0 }, &Position { x: 3, y: 1 });
+        assert_eq!(copied, "rst\nsec");
+
+        let at = Position { x: 5, y: 2 };
+        let end = document.replace_range(&at, &at, &copied);
+        assert_eq!(contents(&document), "first\nsecond\nthirdrst\nsec\n");
+        assert_eq!((end.x, end.y), (3, 3));
+        assert_eq!(document.len(), 4);
+    }
+
+    fn at(cursors: &[Position]) -> Vec<(usize, usize)> {
+        cursors.iter().map(|cursor| (cursor.x, cursor.y)).collect()
+    }
+
+    #[test]
+    fn inserts_at_cursors_on_the_same_row() {
+        let mut document = document("abc\n");
+        let mut cursors = [Position { x: 2, y: 0 }, Position { x: 0, y: 0 }];
+        document.insert_at_cursors(&mut cursors, "XY");
+        assert_eq!(contents(&document), "XYabXYc\n");
+        assert_eq!(at(&cursors), [(6, 0), (2, 0)]);
+
+        document.undo();
+        assert_eq!(contents(&document), "abc\n");
+    }
+
+    #[test]
+    fn inserts_newlines_at_cursors() {
+        let mut document = document("ab\ncd\n");
+        let mut cursors = [Position { x: 1, y: 0 }, Position { x: 1, y: 1 }];
+        document.insert_at_cursors(&mut cursors, "\n");
+        assert_eq!(contents(&document), "a\nb\nc\nd\n");
+        assert_eq!(at(&cursors), [(0, 1), (0, 3)]);
+        assert_eq!(document.highlighter.line_count(), document.len());
+
+        document.undo();
+        assert_eq!(contents(&document), "ab\ncd\n");
+    }
+
+    #[test]
+    fn backspace_merges_cursors_that_meet() {
+        let mut document = document("abcd\n");
+        let mut cursors = [Position { x: 1, y: 0 }, Position { x: 2, y: 0 }];
+        document.backspace_at_cursors(&mut cursors);
+        assert_eq!(contents(&document), "cd\n");
+        assert_eq!(at(&cursors), [(0, 0), (0, 0)]);
+
+        // Cursors on the same spot only delete once.
+        document.undo();
+        let mut cursors = [Position { x: 2, y: 0 }, Position { x: 2, y: 0 }];
+        document.backspace_at_cursors(&mut cursors);
+        assert_eq!(contents(&document), "acd\n");
+        assert_eq!(at(&cursors), [(1, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn joins_rows_at_cursors() {
+        let mut document = document("abc\ndef\n");
+        let mut cursors = [Position { x: 0, y: 1 }, Position { x: 3, y: 0 }];
+        document.backspace_at_cursors(&mut cursors);
+        assert_eq!(contents(&document), "abdef\n");
+        assert_eq!(at(&cursors), [(2, 0), (2, 0)]);
+        document.undo();
+        assert_eq!(contents(&document), "abc\ndef\n");
+
+        let mut cursors = [Position { x: 3, y: 0 }, Position { x: 1, y: 1 }];
+        document.delete_at_cursors(&mut cursors);
+        assert_eq!(contents(&document), "abcdf\n");
+        assert_eq!(at(&cursors), [(3, 0), (4, 0)]);
+        assert_eq!(document.highlighter.line_count(), document.len());
+    }
+
+    #[test]
+    fn undoes_a_multi_cursor_edit_in_one_step() {
+        let mut document = document("one\ntwo\nthree\n");
+        let mut cursors = [
+            Position { x: 0, y: 0 },
+            Position { x: 0, y: 1 },
+            Position { x: 0, y: 2 },
+        ];
+        document.insert_at_cursors(&mut cursors, "- ");
+        document.backspace_at_cursors(&mut cursors);
+        assert_eq!(contents(&document), "-one\n-two\n-three\n");
+
+        document.undo();
+        assert_eq!(contents(&document), "- one\n- two\n- three\n");
+        document.undo();
+        assert_eq!(contents(&document), "one\ntwo\nthree\n");
+        document.redo();
+        assert_eq!(contents(&document), "- one\n- two\n- three\n");
     }
 }
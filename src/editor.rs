@@ -1,21 +1,65 @@
 #![warn(clippy::all, clippy::pedantic)]
 
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use crossterm::style::Color;
-
-use std::{env, process};
+use crossterm::event::{
+    Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use crossterm::style::{Attribute, Color};
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::iter;
+use std::mem;
+use std::path::Path;
 use std::time::Duration;
 use std::time::Instant;
+use std::{env, process};
 
+use crate::cli;
+use crate::grep;
+use crate::layout;
+use crate::quickfix;
+use crate::tab_line;
+use crate::Args;
+use crate::Buffer;
+use crate::Clipboard;
 use crate::Document;
+use crate::Explorer;
+use crate::Finder;
+use crate::History;
+use crate::Layout;
+use crate::Picker;
+use crate::PromptHistory;
+use crate::PromptKind;
+use crate::PromptLine;
+use crate::Query;
+use crate::Quickfix;
+use crate::Rect;
 use crate::Row;
+use crate::SearchOptions;
+use crate::SplitDirection;
 use crate::Terminal;
 
 const STATUS_FG_COLOR: Color = Color::Black;
 const STATUS_BG_COLOR: Color = Color::White;
+const INACTIVE_STATUS_BG_COLOR: Color = Color::DarkGrey;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const QUIT_TIMES: u8 = 1;
-const TAB_SIZE: u8 = 4;
+const HELP: &str =
+    "HELP: Ctrl-F = find | Ctrl-R = replace | Ctrl-S = save | Ctrl-Z = undo | Ctrl-Y = redo | Ctrl-Q = quit";
+const EXPLORER_HELP: &str =
+    "Explorer: Enter = open | a = new | r = rename | d = delete | . = hidden | i = ignored | ESC = back";
+/// The widest the explorer sidebar gets, divider not included.
+const EXPLORER_WIDTH: usize = 30;
+/// How often the files shown in the explorer are checked for changes.
+const EXPLORER_POLL: Duration = Duration::from_secs(1);
+/// How often the file finder takes in the files found so far.
+const FINDER_POLL: Duration = Duration::from_millis(100);
+/// How often a running build is checked for its output.
+const BUILD_POLL: Duration = Duration::from_millis(100);
+/// The most matches the file finder lists.
+const FINDER_LIMIT: usize = 1000;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -23,12 +67,36 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+/// Display cells `left..right` of rows `top..=bottom`.
+struct Block {
+    top: usize,
+    bottom: usize,
+    left: usize,
+    right: usize,
+}
+
+/// A view onto a buffer, kept in the editor's own fields while focused.
+struct Pane {
+    buffer: usize,
+    cursor_position: Position,
+    offset: Position,
+}
+
+/// What is drawn over the text of the focused pane.
+#[derive(Default)]
+struct Marks {
+    reversed: Vec<(Position, Position)>,
+    underlined: Vec<(Position, Position)>,
+    block: Option<Block>,
+    cursors: Vec<Position>,
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
@@ -51,36 +119,118 @@ pub struct Editor {
     document: Document,
     status_message: StatusMessage,
     quit_times: u8,
-    search_keyword: Option<String>,
+    search_query: Option<Query>,
+    search_match: Option<(Position, Position)>,
+    search_options: SearchOptions,
+    prompt_status: Option<String>,
+    prompt_cursor: Option<usize>,
+    selection_anchor: Option<Position>,
+    clipboard: Clipboard,
+    prompt_history: PromptHistory,
+    extra_cursors: Vec<Position>,
+    block_selection: bool,
+    block_clipboard: Option<String>,
+    /// The command line and config file options, applied to every file opened.
+    args: Args,
+    /// The open buffers other than the one being shown.
+    buffers: Vec<Buffer>,
+    /// Where the buffer being shown comes in the list of all buffers.
+    buffer_index: usize,
+    picker: Option<Picker>,
+    panes: Vec<Pane>,
+    layout: Layout,
+    focused: usize,
+    explorer: Option<Explorer>,
+    /// Whether keys go to the explorer rather than the focused pane.
+    explorer_focused: bool,
+    project_search: Option<grep::Results>,
+    quickfix: Quickfix,
+    running_build: Option<quickfix::Build>,
 }
 
 impl Editor {
     pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status =
-            String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
+        let mut initial_status = String::from(HELP);
+
+        let args = Args::parse(env::args().skip(1));
 
-        let document = if let Some(file_name) = args.get(1) {
-            if let Ok(doc) = Document::open(&file_name) {
-                doc
+        let mut documents = Vec::new();
+        for (file_name, location) in &args.files {
+            if let Ok(doc) = Document::open(file_name) {
+                documents.push((doc, *location));
             } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+                initial_status = format!("ERR: Could not open file: {file_name}");
             }
-        } else {
-            Document::default()
-        };
+        }
+        if documents.is_empty() {
+            documents.push((Document::default(), None));
+        }
+        for (document, _) in &mut documents {
+            configure(document, &args);
+        }
 
-        Self {
+        // Compiler output is read before the terminal is taken over, since it
+        // may come from the standard input.
+        let compiler_output = args.quickfix.as_deref().map(|path| {
+            quickfix::read(path).unwrap_or_else(|error| {
+                eprintln!("lekh: can't read '{path}': {error}");
+                process::exit(2);
+            })
+        });
+
+        let (document, location) = documents.remove(0);
+        let mut terminal = Terminal::default();
+        terminal.set_tab_line(args.tab_line);
+        let mut editor = Self {
             should_quit: false,
-            terminal: Terminal::default(),
+            terminal,
             cursor_position: Position::default(),
             offset: Position::default(),
             document,
             status_message: StatusMessage::from(initial_status),
             quit_times: QUIT_TIMES,
-            search_keyword: None,
+            search_query: None,
+            search_match: None,
+            search_options: SearchOptions::default(),
+            prompt_status: None,
+            prompt_cursor: None,
+            selection_anchor: None,
+            clipboard: Clipboard::default(),
+            prompt_history: PromptHistory::load(),
+            extra_cursors: Vec::new(),
+            block_selection: false,
+            block_clipboard: None,
+            buffers: Vec::new(),
+            buffer_index: 0,
+            picker: None,
+            panes: vec![Pane {
+                buffer: 0,
+                cursor_position: Position::default(),
+                offset: Position::default(),
+            }],
+            layout: Layout::default(),
+            focused: 0,
+            explorer: None,
+            explorer_focused: false,
+            project_search: None,
+            quickfix: Quickfix::default(),
+            running_build: None,
+            args,
+        };
+        editor.jump_to_location(location);
+        for (document, location) in documents {
+            let index = editor.add_buffer(document);
+            editor.switch_buffer(index);
+            editor.jump_to_location(location);
+        }
+        editor.switch_buffer(0);
+        if let Some(output) = compiler_output {
+            editor.set_quickfix(quickfix::parse(&output));
+            if !editor.quickfix.entries.is_empty() {
+                editor.go_to_quickfix(0);
+            }
         }
+        editor
     }
 
     pub fn run(&mut self) {
@@ -89,6 +239,9 @@ impl Editor {
             process::exit(102);
         }
         Terminal::into_raw_mode();
+        if self.args.mouse && Terminal::enable_mouse().is_err() {
+            Terminal::cleanup_and_exit(Some("Error: Couldn't turn on mouse support."), 101);
+        }
 
         loop {
             if self.refresh_screen().is_err() {
@@ -105,52 +258,436 @@ impl Editor {
         }
     }
 
+    /// Offers to save every unsaved buffer, then quits unless that is cancelled.
     fn quit(&mut self) -> Result<(), std::io::Error> {
-        let mut quit = true;
-
-        if self.document.is_dirty() {
-            let mut result;
-            loop {
-                result = self.prompt("Save Modified Buffer? (Y or N): ", |_, _, _| {})?;
-
-                if let Some(response) = result {
-                    match &*response {
-                        "y" | "Y" => {
-                            if self.save().is_err() {
-                                quit = false;
-                            }
-                            break;
-                        }
-                        "n" | "N" => {
-                            break;
-                        }
-                        _ => (),
+        for index in 0..self.buffer_count() {
+            if !self.buffer_document(index).is_dirty() {
+                continue;
+            }
+            self.switch_buffer(index);
+            if !self.ask_to_save()? {
+                return Ok(());
+            }
+        }
+        self.should_quit = true;
+        Ok(())
+    }
+
+    /// Returns false if the question was cancelled or saving failed.
+    fn ask_to_save(&mut self) -> Result<bool, std::io::Error> {
+        let prompt = format!(
+            "Save modified buffer {}? (Y or N): ",
+            self.document
+                .get_file_name()
+                .as_deref()
+                .unwrap_or("[No Name]")
+        );
+        loop {
+            let Some(response) = self.prompt(&prompt, None, |_, _, _| {})? else {
+                return Ok(false);
+            };
+            match &*response {
+                "y" | "Y" => return Ok(self.save().is_ok()),
+                "n" | "N" => return Ok(true),
+                _ => (),
+            }
+        }
+    }
+
+    fn buffer_count(&self) -> usize {
+        self.buffers.len() + 1
+    }
+
+    fn buffer_document(&self, index: usize) -> &Document {
+        match self.buffer_slot(index) {
+            Some(slot) => &self.buffers[slot].document,
+            None => &self.document,
+        }
+    }
+
+    fn buffer_document_mut(&mut self, index: usize) -> &mut Document {
+        match self.buffer_slot(index) {
+            Some(slot) => &mut self.buffers[slot].document,
+            None => &mut self.document,
+        }
+    }
+
+    /// Returns None for the buffer being shown.
+    fn buffer_slot(&self, index: usize) -> Option<usize> {
+        match index.cmp(&self.buffer_index) {
+            Ordering::Less => Some(index),
+            Ordering::Equal => None,
+            Ordering::Greater => Some(index - 1),
+        }
+    }
+
+    fn add_buffer(&mut self, document: Document) -> usize {
+        self.buffers.push(Buffer::new(document));
+        self.buffer_count() - 1
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.buffer_index || index >= self.buffer_count() {
+            return;
+        }
+        let slot = if index > self.buffer_index {
+            index - 1
+        } else {
+            index
+        };
+        let next = self.buffers.remove(slot);
+        let previous = Buffer {
+            document: mem::replace(&mut self.document, next.document),
+            cursor_position: mem::replace(&mut self.cursor_position, next.cursor_position),
+            offset: mem::replace(&mut self.offset, next.offset),
+            selection_anchor: mem::replace(&mut self.selection_anchor, next.selection_anchor),
+            search_query: mem::replace(&mut self.search_query, next.search_query),
+            search_match: mem::replace(&mut self.search_match, next.search_match),
+        };
+        let previous_slot = if index > self.buffer_index {
+            self.buffer_index
+        } else {
+            self.buffer_index - 1
+        };
+        self.buffers.insert(previous_slot, previous);
+        self.buffer_index = index;
+
+        self.extra_cursors.clear();
+        self.block_selection = false;
+        self.scroll();
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.buffer_count();
+        if count == 1 {
+            self.status_message = StatusMessage::from("No other buffers.".to_string());
+            return;
+        }
+        let index = if forward {
+            (self.buffer_index + 1) % count
+        } else {
+            (self.buffer_index + count - 1) % count
+        };
+        self.switch_buffer(index);
+    }
+
+    fn pick_buffer(&mut self) -> Result<(), std::io::Error> {
+        let names = (0..self.buffer_count())
+            .map(|index| {
+                let name = Buffer::name(self.buffer_document(index));
+                format!("{:>3}  {name}", index + 1)
+            })
+            .collect();
+        let picked = self.pick(
+            "Buffers (Up/Down to choose, Enter to show, ESC to cancel)",
+            names,
+            self.buffer_index,
+        )?;
+        if let Some(index) = picked {
+            self.switch_buffer(index);
+        }
+        Ok(())
+    }
+
+    fn file_buffer(&self, path: &Path) -> Option<usize> {
+        (0..self.buffer_count()).find(|index| {
+            self.buffer_document(*index)
+                .get_file_name()
+                .is_some_and(|file_name| same_file(Path::new(&file_name), path))
+        })
+    }
+
+    /// Returns false if the file couldn't be opened.
+    fn open_file(&mut self, path: &Path) -> bool {
+        let index = if let Some(index) = self.file_buffer(path) {
+            index
+        } else {
+            let file_name = path.to_string_lossy();
+            let Ok(mut document) = Document::open(&file_name) else {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not open file: {file_name}"));
+                return false;
+            };
+            configure(&mut document, &self.args);
+            let index = self.add_buffer(document);
+            self.mark_quickfix(index);
+            index
+        };
+        self.switch_buffer(index);
+        true
+    }
+
+    /// Starts the build command, asking for one if none was configured.
+    fn build(&mut self) -> Result<(), std::io::Error> {
+        if let Some(build) = &self.running_build {
+            self.status_message =
+                StatusMessage::from(format!("{} is still running.", build.command));
+            return Ok(());
+        }
+        let command = if let Some(command) = self.args.build.clone() {
+            command
+        } else {
+            let Some(command) =
+                self.prompt("Build command: ", Some(PromptKind::Build), |_, _, _| {})?
+            else {
+                return Ok(());
+            };
+            command
+        };
+
+        self.status_message = StatusMessage::from(format!("Running {command}..."));
+        self.running_build = Some(quickfix::Build::start(&command));
+        Ok(())
+    }
+
+    /// Returns true if the running build was done and its output listed.
+    fn finish_build(&mut self) -> bool {
+        let Some(build) = &self.running_build else {
+            return false;
+        };
+        let Some(output) = build.output() else {
+            return false;
+        };
+        let command = build.command.clone();
+        self.running_build = None;
+        match output {
+            Ok(output) => self.set_quickfix(quickfix::parse(&output)),
+            Err(error) => {
+                self.status_message =
+                    StatusMessage::from(format!("ERR: Could not run {command}: {error}"));
+            }
+        }
+        true
+    }
+
+    fn set_quickfix(&mut self, entries: Vec<quickfix::Entry>) {
+        self.quickfix = Quickfix::new(entries);
+        for index in 0..self.buffer_count() {
+            self.mark_quickfix(index);
+        }
+        let count = self.quickfix.entries.len();
+        self.status_message = StatusMessage::from(if count == 0 {
+            "No error locations found.".to_string()
+        } else {
+            format!("Found {count} error locations. Alt-N/Alt-P go to the next/previous one, Ctrl-L lists them.")
+        });
+    }
+
+    /// Marks the error locations so that they follow edits to the file.
+    fn mark_quickfix(&mut self, index: usize) {
+        let Some(file_name) = self.buffer_document(index).get_file_name() else {
+            return;
+        };
+        let mut positions = Vec::new();
+        for entry in &mut self.quickfix.entries {
+            if same_file(Path::new(&entry.file), Path::new(&file_name)) {
+                entry.mark = Some(positions.len());
+                positions.push(Position {
+                    x: entry.x,
+                    y: entry.y,
+                });
+            }
+        }
+        self.buffer_document_mut(index).set_marks(&positions);
+    }
+
+    /// Returns where error location `index` has moved to since the build.
+    fn quickfix_position(&self, index: usize) -> Position {
+        let entry = &self.quickfix.entries[index];
+        entry
+            .mark
+            .and_then(|mark| {
+                let buffer = self.file_buffer(Path::new(&entry.file))?;
+                self.buffer_document(buffer).mark(mark)
+            })
+            .unwrap_or(Position {
+                x: entry.x,
+                y: entry.y,
+            })
+    }
+
+    fn go_to_quickfix(&mut self, index: usize) {
+        self.quickfix.current = Some(index);
+        let file = self.quickfix.entries[index].file.clone();
+        self.explorer_focused = false;
+        if !self.open_file(Path::new(&file)) {
+            return;
+        }
+        let Position { x, y } = self.quickfix_position(index);
+        self.jump_to(y, Some(x));
+        self.status_message = StatusMessage::from(format!(
+            "({}/{}) {}",
+            index + 1,
+            self.quickfix.entries.len(),
+            self.quickfix.entries[index].message
+        ));
+    }
+
+    fn step_quickfix(&mut self, forward: bool) {
+        if self.quickfix.entries.is_empty() {
+            self.status_message = StatusMessage::from("No error locations.".to_string());
+        } else if let Some(index) = self.quickfix.step(forward) {
+            self.go_to_quickfix(index);
+        } else {
+            self.status_message = StatusMessage::from("No more error locations.".to_string());
+        }
+    }
+
+    fn pick_quickfix(&mut self) -> Result<(), std::io::Error> {
+        if self.quickfix.entries.is_empty() {
+            self.status_message = StatusMessage::from("No error locations.".to_string());
+            return Ok(());
+        }
+        let items = (0..self.quickfix.entries.len())
+            .map(|index| {
+                let Position { x, y } = self.quickfix_position(index);
+                let entry = &self.quickfix.entries[index];
+                format!("{}:{}:{}: {}", entry.file, y + 1, x + 1, entry.message)
+            })
+            .collect();
+        let picked = self.pick(
+            "Error locations (Up/Down to choose, Enter to go there, ESC to cancel)",
+            items,
+            self.quickfix.current.unwrap_or(0),
+        )?;
+        if let Some(index) = picked {
+            self.go_to_quickfix(index);
+        }
+        Ok(())
+    }
+
+    /// Walks the working directory in the background while the user types.
+    fn find_file(&mut self) -> Result<(), std::io::Error> {
+        let prompt = "Find file: ";
+        let mut finder = Finder::default();
+        let mut line = PromptLine::default();
+        self.picker = Some(Picker::new(Vec::new(), 0));
+
+        let mut changed = true;
+        let picked = loop {
+            if finder.receive() {
+                changed = true;
+                if let Some(picker) = self.picker.as_mut() {
+                    picker.set_items(finder.matches(FINDER_LIMIT));
+                }
+            }
+            if changed {
+                let searching = if finder.is_done() { "" } else { "..." };
+                let status = format!(
+                    "{}/{}{searching}",
+                    finder.match_count(),
+                    finder.file_count()
+                );
+                let width = self.terminal.get_size().width as usize;
+                let (visible, cursor) =
+                    line.view(width.saturating_sub(Row::width(prompt) + Row::width(&status) + 1));
+                self.prompt_status = Some(status);
+                self.status_message = StatusMessage::from(format!("{prompt}{visible}"));
+                self.prompt_cursor = Some(Row::width(prompt) + cursor);
+                self.refresh_screen()?;
+            }
+
+            let event =
+                Terminal::poll_event(FINDER_POLL, |width, height| self.resize(width, height))?;
+            changed = event.is_some();
+            let Some(Event::Key(key_event)) = event else {
+                continue;
+            };
+            let height = self.terminal.get_size().height as usize;
+            let Some(picker) = self.picker.as_mut() else {
+                break None;
+            };
+            match key_event.code {
+                KeyCode::Enter => break picker.selected_item().map(str::to_string),
+                KeyCode::Esc => break None,
+                code @ (KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown) => {
+                    picker.move_selection(code, height);
+                }
+                _ => {
+                    let query = line.text().clone();
+                    line.edit(key_event);
+                    if *line.text() != query {
+                        finder.set_query(line.text());
+                        *picker = Picker::new(finder.matches(FINDER_LIMIT), 0);
                     }
-                } else {
-                    quit = false;
-                    break;
                 }
             }
+        };
+
+        self.picker = None;
+        self.prompt_cursor = None;
+        self.prompt_status = None;
+        self.status_message = StatusMessage::from(String::new());
+        if let Some(file_name) = picked {
+            self.explorer_focused = false;
+            self.open_file(Path::new(&file_name));
         }
-        self.should_quit = quit;
         Ok(())
     }
 
+    /// Returns the index picked with Enter, or None on ESC.
+    fn pick(
+        &mut self,
+        prompt: &str,
+        items: Vec<String>,
+        selected: usize,
+    ) -> Result<Option<usize>, std::io::Error> {
+        let picked = self.pick_with_keys(prompt, items, selected, &[])?;
+        Ok(picked.map(|(index, _)| index))
+    }
+
+    /// Like `pick`, but also ends on and returns a Ctrl key in `control_keys`.
+    fn pick_with_keys(
+        &mut self,
+        prompt: &str,
+        items: Vec<String>,
+        selected: usize,
+        control_keys: &[char],
+    ) -> Result<Option<(usize, Option<char>)>, std::io::Error> {
+        self.picker = Some(Picker::new(items, selected));
+        let picked = loop {
+            self.status_message = StatusMessage::from(prompt.to_string());
+            self.refresh_screen()?;
+
+            let key_event = self.read_key()?;
+            let height = self.terminal.get_size().height as usize;
+            let Some(picker) = self.picker.as_mut() else {
+                break None;
+            };
+            match key_event.code {
+                KeyCode::Enter => break picker.selected().map(|index| (index, None)),
+                KeyCode::Esc => break None,
+                KeyCode::Char(ch)
+                    if key_event.modifiers == KeyModifiers::CONTROL
+                        && control_keys.contains(&ch.to_ascii_lowercase()) =>
+                {
+                    break picker
+                        .selected()
+                        .map(|index| (index, Some(ch.to_ascii_lowercase())));
+                }
+                code => picker.move_selection(code, height),
+            }
+        };
+        self.picker = None;
+        self.status_message = StatusMessage::from(String::new());
+        Ok(picked)
+    }
+
     fn save(&mut self) -> Result<(), &str> {
         if self.document.get_file_name().is_none() {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            match new_name {
-                Some(file_name) => self.document.set_file_name(file_name),
-                None => {
-                    self.status_message = StatusMessage::from("Save aborted.".to_string());
-                    return Err("Can't save file.");
-                }
+            let new_name = self
+                .prompt("Save as: ", Some(PromptKind::SaveAs), |_, _, _| {})
+                .unwrap_or(None);
+            if let Some(file_name) = new_name {
+                self.document.set_file_name(file_name);
+            } else {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Err("Can't save file.");
             }
         }
 
         if self.document.save().is_ok() {
             self.status_message = StatusMessage::from("File saved successfully.".to_string());
-            self.document.highlight();
         } else {
             self.status_message = StatusMessage::from("Error writing file!".to_string());
             return Err("Can't save file.");
@@ -160,124 +697,1436 @@ impl Editor {
     }
 
     fn search(&mut self) {
+        self.extra_cursors.clear();
         let old_position = self.cursor_position.clone();
         let mut direction = SearchDirection::Forward;
 
         let mut found = false;
+        let previous_query = self.search_query.take();
+        self.set_search_query("");
 
         let query = self
             .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
-                |editor, key, query| {
+                "Search (ESC to cancel, Ctrl-N/Ctrl-P to navigate): ",
+                Some(PromptKind::Search),
+                |editor, key_event, query| {
                     let mut moved = false;
                     found = false;
-                    editor.search_keyword = Some(query.clone());
+                    editor.toggle_search_option(key_event);
+                    editor.set_search_query(query);
 
-                    match key {
-                        KeyCode::Right | KeyCode::Down => {
+                    match key_event.code {
+                        KeyCode::Char('n') if key_event.modifiers == KeyModifiers::CONTROL => {
                             direction = SearchDirection::Forward;
                             editor.move_cursor(KeyCode::Right);
                             moved = true;
                         }
-                        KeyCode::Left | KeyCode::Up => {
+                        KeyCode::Char('p') if key_event.modifiers == KeyModifiers::CONTROL => {
                             direction = SearchDirection::Backward;
                         }
                         _ => direction = SearchDirection::Forward,
                     }
 
-                    if let Some(position) =
+                    editor.search_match = None;
+                    let found_match = editor.search_query.as_ref().and_then(|query| {
                         editor
                             .document
-                            .find(&query, &editor.cursor_position, direction)
-                    {
-                        editor.cursor_position = position;
-                        editor.scroll();
+                            .find_wrapping(query, &editor.cursor_position, direction)
+                    });
+                    let mut wrapped = false;
+                    if let Some((start, end, wrapped_around)) = found_match {
+                        editor.search_match = Some((start.clone(), end.clone()));
+                        editor.show_match(start, end);
 
                         found = true;
+                        wrapped = wrapped_around;
                     } else if moved {
                         editor.move_cursor(KeyCode::Left);
                     }
+                    editor.count_matches(wrapped);
                 },
             )
             .unwrap_or(None);
 
-        if query.is_none() {
-            self.cursor_position = old_position;
-            self.scroll();
-        } else if !found {
-            self.status_message = StatusMessage::from("No results found.".to_string());
-            self.cursor_position = old_position;
-            self.scroll();
+        if query.is_none() {
+            self.cursor_position = old_position;
+            self.scroll();
+        } else if !found {
+            self.status_message = StatusMessage::from("No results found.".to_string());
+            self.cursor_position = old_position;
+            self.scroll();
+        }
+        // The buffer keeps the last query that was searched for.
+        if query.is_none() || !found {
+            self.search_query = previous_query;
+        }
+        self.search_match = None;
+        self.prompt_status = None;
+    }
+
+    /// Scrolls so that as much of the match as fits is visible.
+    fn show_match(&mut self, start: Position, end: Position) {
+        self.cursor_position = end;
+        self.scroll();
+        self.cursor_position = start;
+        self.scroll();
+    }
+
+    /// Shows which match is the current one, and whether the search wrapped.
+    fn count_matches(&mut self, wrapped: bool) {
+        let Some(query) = &self.search_query else {
+            return;
+        };
+        let counter = if let Some((current, _)) = &self.search_match {
+            let (number, total) = self.document.count_matches(query, current);
+            format!("match {number} of {total}")
+        } else {
+            "no matches".to_string()
+        };
+        let notice = if wrapped { "search wrapped, " } else { "" };
+        let label = self.search_options.label();
+        self.prompt_status = Some(format!("{notice}{counter} {label}").trim_end().to_string());
+    }
+
+    /// Handles Alt-R, Alt-C, Alt-S and Alt-W in a search prompt.
+    fn toggle_search_option(&mut self, key_event: KeyEvent) {
+        if !key_event.modifiers.contains(KeyModifiers::ALT) {
+            return;
+        }
+        let options = &mut self.search_options;
+        match key_event.code {
+            KeyCode::Char('r' | 'R') => options.regex = !options.regex,
+            KeyCode::Char('c' | 'C') => options.ignore_case = !options.ignore_case,
+            KeyCode::Char('s' | 'S') => options.smart_case = !options.smart_case,
+            KeyCode::Char('w' | 'W') => options.whole_word = !options.whole_word,
+            _ => (),
+        }
+    }
+
+    fn set_search_query(&mut self, text: &str) {
+        self.search_query = None;
+        let label = self.search_options.label();
+        self.prompt_status = if label.is_empty() {
+            Some("Alt-R/C/S/W = regex/case/smart/word".to_string())
+        } else {
+            Some(label)
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        match Query::new(text, self.search_options) {
+            Ok(query) => self.search_query = Some(query),
+            Err(_) => self.prompt_status = Some("[invalid regex]".to_string()),
+        }
+    }
+
+    /// Enter with nothing typed lists the matches of the last search again.
+    fn project_search(&mut self) -> Result<(), std::io::Error> {
+        let previous_query = self.search_query.take();
+        self.set_search_query("");
+        let text = self.read_prompt(
+            "Search files (ESC to cancel, Enter alone for the last results): ",
+            Some(PromptKind::Search),
+            |editor, key_event, text| {
+                editor.toggle_search_option(key_event);
+                editor.set_search_query(text);
+            },
+        )?;
+        self.prompt_status = None;
+        let query = mem::replace(&mut self.search_query, previous_query);
+
+        match text.as_deref() {
+            None => return Ok(()),
+            Some("") => (),
+            Some(_) => {
+                let Some(query) = query else {
+                    self.status_message = StatusMessage::from("Invalid regex.".to_string());
+                    return Ok(());
+                };
+                self.status_message = StatusMessage::from("Searching...".to_string());
+                self.refresh_screen()?;
+                let hits = self.search_files(&query);
+                self.project_search = Some(grep::Results {
+                    query,
+                    hits,
+                    selected: 0,
+                });
+            }
+        }
+        self.show_project_results()
+    }
+
+    /// Searches the text of open buffers rather than their saved files.
+    fn search_files(&self, query: &Query) -> Vec<grep::Hit> {
+        let open: HashMap<_, _> = (0..self.buffer_count())
+            .filter_map(|index| {
+                let file_name = self.buffer_document(index).get_file_name()?;
+                Some((fs::canonicalize(file_name).ok()?, index))
+            })
+            .collect();
+        grep::search(query, |path| {
+            if open.is_empty() {
+                return None;
+            }
+            let index = open.get(&fs::canonicalize(path).ok()?)?;
+            Some(self.buffer_document(*index).contents())
+        })
+    }
+
+    fn show_project_results(&mut self) -> Result<(), std::io::Error> {
+        let Some(results) = &self.project_search else {
+            self.status_message = StatusMessage::from("No files searched yet.".to_string());
+            return Ok(());
+        };
+        if results.hits.is_empty() {
+            self.status_message = StatusMessage::from("No results found.".to_string());
+            return Ok(());
+        }
+        let prompt = format!(
+            "{} matches in {} files (Enter to open, Ctrl-R to replace all, ESC to close)",
+            results.hits.len(),
+            results.files().len()
+        );
+        let items = results.hits.iter().map(grep::Hit::label).collect();
+        let selected = results.selected;
+
+        match self.pick_with_keys(&prompt, items, selected, &['r'])? {
+            Some((_, Some(_))) => self.replace_in_files()?,
+            Some((index, None)) => {
+                let Some(results) = self.project_search.as_mut() else {
+                    return Ok(());
+                };
+                results.selected = index;
+                let hit = &results.hits[index];
+                let (file, y, x) = (hit.file.clone(), hit.y, hit.x);
+                self.explorer_focused = false;
+                if self.open_file(Path::new(&file)) {
+                    self.jump_to(y, Some(x));
+                }
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Open buffers are changed and left unsaved, other files are written.
+    fn replace_in_files(&mut self) -> Result<(), std::io::Error> {
+        let Some(results) = self.project_search.take() else {
+            return Ok(());
+        };
+        let Some(template) =
+            self.read_prompt("Replace with: ", Some(PromptKind::Replace), |_, _, _| {})?
+        else {
+            self.project_search = Some(results);
+            return Ok(());
+        };
+
+        let files = results.files();
+        let mut preview = Vec::new();
+        for file in &files {
+            let text = match self.file_buffer(Path::new(file)) {
+                Some(index) => Some(self.buffer_document(index).contents()),
+                None => fs::read_to_string(file).ok(),
+            };
+            let Some(text) = text else {
+                continue;
+            };
+            for (y, before, after) in grep::changed_rows(&results.query, &text, &template) {
+                for (sign, rows) in [("-", before), ("+", after)] {
+                    for (offset, row) in rows.split('\n').enumerate() {
+                        preview.push(format!("{sign} {file}:{}: {}", y + offset + 1, row.trim()));
+                    }
+                }
+            }
+        }
+        let prompt = format!(
+            "Replace {} matches in {} files? (Enter to replace, ESC to cancel)",
+            results.hits.len(),
+            files.len()
+        );
+        if self.pick(&prompt, preview, 0)?.is_none() {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            self.project_search = Some(results);
+            return Ok(());
+        }
+
+        let (mut replaced, mut changed, mut unsaved) = (0, 0, 0);
+        let mut failed = Vec::new();
+        for file in files {
+            let path = Path::new(file);
+            let count = if let Some(index) = self.file_buffer(path) {
+                let document = self.buffer_document_mut(index);
+                if document.is_read_only() {
+                    failed.push(file);
+                    continue;
+                }
+                let (count, _) =
+                    document.replace_all(&results.query, &Position::default(), &template);
+                unsaved += usize::from(count > 0);
+                count
+            } else if let Ok(count) = grep::replace_in_file(path, &results.query, &template) {
+                count
+            } else {
+                failed.push(file);
+                continue;
+            };
+            replaced += count;
+            changed += usize::from(count > 0);
+        }
+        self.clamp_cursor();
+        self.scroll();
+        for buffer in &mut self.buffers {
+            buffer.clamp();
+        }
+
+        let mut message = vec![format!("Replaced {replaced} matches in {changed} files.")];
+        if unsaved > 0 {
+            message.push(format!("Buffers left unsaved: {unsaved}."));
+        }
+        if !failed.is_empty() {
+            message.push(format!("Could not change {}.", failed.join(", ")));
+        }
+        self.status_message = StatusMessage::from(message.join(" "));
+        Ok(())
+    }
+
+    fn replace(&mut self) -> Result<(), std::io::Error> {
+        self.extra_cursors.clear();
+        self.selection_anchor = None;
+        self.block_selection = false;
+        let previous_query = self.search_query.take();
+        self.set_search_query("");
+
+        let text = self.prompt(
+            "Replace (ESC to cancel): ",
+            Some(PromptKind::Search),
+            |editor, key_event, text| {
+                editor.toggle_search_option(key_event);
+                editor.set_search_query(text);
+            },
+        )?;
+        self.prompt_status = None;
+
+        let query = self.search_query.clone();
+        if text.is_none() {
+            self.search_query = previous_query;
+            return Ok(());
+        }
+        let Some(query) = query else {
+            self.status_message = StatusMessage::from("Invalid regex.".to_string());
+            self.search_query = previous_query;
+            return Ok(());
+        };
+
+        if let Some(template) =
+            self.read_prompt("Replace with: ", Some(PromptKind::Replace), |_, _, _| {})?
+        {
+            let replaced = self.replace_matches(&query, &template)?;
+            self.status_message = StatusMessage::from(format!("Replaced {replaced} occurrences."));
+        } else {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+        }
+        self.search_query = previous_query;
+        Ok(())
+    }
+
+    /// Asks about each match after the cursor; the replacements undo as one step.
+    fn replace_matches(&mut self, query: &Query, template: &str) -> Result<usize, std::io::Error> {
+        let mut replaced = 0;
+        let mut at = self.cursor_position.clone();
+
+        self.document.begin_edit_group();
+        while let Some((start, end)) = self.document.find(query, &at, SearchDirection::Forward) {
+            self.search_match = Some((start.clone(), end.clone()));
+            self.show_match(start.clone(), end.clone());
+            self.status_message = StatusMessage::from(
+                "Replace this match? (y = yes, n = no, a = all, q = quit)".to_string(),
+            );
+            self.refresh_screen()?;
+
+            let replace = match self.read_key()?.code {
+                KeyCode::Char('y' | 'Y') => true,
+                KeyCode::Char('n' | 'N') => false,
+                KeyCode::Char('a' | 'A') => {
+                    let (count, end) = self.document.replace_all(query, &start, template);
+                    replaced += count;
+                    at = end;
+                    break;
+                }
+                KeyCode::Char('q' | 'Q') | KeyCode::Esc => break,
+                _ => continue,
+            };
+
+            at = if replace {
+                replaced += 1;
+                self.document.replace(query, &start, &end, template)
+            } else {
+                end
+            };
+        }
+        self.document.end_edit_group();
+
+        self.search_match = None;
+        self.cursor_position = at;
+        self.scroll();
+        Ok(replaced)
+    }
+
+    fn go_to(&mut self) -> Result<(), std::io::Error> {
+        let Some(text) = self.prompt(
+            "Go to (line, line:col, +N, -N or N%): ",
+            Some(PromptKind::GoTo),
+            |_, _, _| {},
+        )?
+        else {
+            return Ok(());
+        };
+
+        if let Some((y, x)) = parse_go_to(&text, self.cursor_position.y, self.document.len()) {
+            self.jump_to(y, x);
+        } else {
+            self.status_message = StatusMessage::from(format!("Invalid location: {text}"));
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self) {
+        self.selection_anchor = None;
+        self.extra_cursors.clear();
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to undo.".to_string());
+        }
+    }
+
+    fn redo(&mut self) {
+        self.selection_anchor = None;
+        self.extra_cursors.clear();
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to redo.".to_string());
+        }
+    }
+
+    fn selection(&self) -> Option<(Position, Position)> {
+        if self.block_selection {
+            return None;
+        }
+        let anchor = self.selection_anchor.clone()?;
+        let cursor = self.cursor_position.clone();
+
+        match (anchor.y, anchor.x).cmp(&(cursor.y, cursor.x)) {
+            Ordering::Less => Some((anchor, cursor)),
+            Ordering::Greater => Some((cursor, anchor)),
+            Ordering::Equal => None,
+        }
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.selection_anchor = None;
+
+        if let Some((start, end)) = selection {
+            self.document.delete_range(&start, &end);
+            self.cursor_position = start;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn copy(&mut self) {
+        if let Some(block) = self.block() {
+            let text = self.block_text(&block);
+            self.block_clipboard = Some(text.clone());
+            if self.clipboard.copy(&text, &mut self.terminal).is_ok() {
+                self.status_message = StatusMessage::from("Copied block.".to_string());
+            } else {
+                self.status_message =
+                    StatusMessage::from("Copied block inside the editor only.".to_string());
+            }
+        } else if let Some((start, end)) = self.selection() {
+            let text = self.document.text_range(&start, &end);
+            if self.clipboard.copy(&text, &mut self.terminal).is_ok() {
+                self.status_message = StatusMessage::from("Copied selection.".to_string());
+            } else {
+                self.status_message =
+                    StatusMessage::from("Copied selection inside the editor only.".to_string());
+            }
+        } else {
+            self.status_message = StatusMessage::from("Nothing selected.".to_string());
+        }
+    }
+
+    fn cut(&mut self) {
+        if let Some(block) = self.block() {
+            self.copy();
+            self.delete_block(&block);
+        } else if self.selection().is_some() {
+            self.copy();
+            self.delete_selection();
+        } else {
+            self.status_message = StatusMessage::from("Nothing selected.".to_string());
+        }
+    }
+
+    fn paste(&mut self) {
+        let text = self.clipboard.paste();
+        if text.is_empty() {
+            self.status_message = StatusMessage::from("Clipboard is empty.".to_string());
+            return;
+        }
+        if !self.extra_cursors.is_empty() {
+            self.edit_at_cursors(|document, cursors| document.insert_at_cursors(cursors, &text));
+            return;
+        }
+        if self.block_clipboard.as_ref() == Some(&text) {
+            self.paste_block(&text);
+            return;
+        }
+        let cursor = self.cursor_position.clone();
+        let (start, end) = self.selection().unwrap_or((cursor.clone(), cursor));
+        self.selection_anchor = None;
+        self.cursor_position = self.document.replace_range(&start, &end, &text);
+    }
+
+    fn block(&self) -> Option<Block> {
+        if !self.block_selection {
+            return None;
+        }
+        let anchor = self.selection_anchor.as_ref()?;
+        let cursor = &self.cursor_position;
+
+        let anchor_cell = self.cell_of(anchor);
+        let cursor_cell = self.cell_of(cursor);
+
+        Some(Block {
+            top: anchor.y.min(cursor.y),
+            bottom: anchor.y.max(cursor.y),
+            left: anchor_cell.min(cursor_cell),
+            right: anchor_cell.max(cursor_cell),
+        })
+    }
+
+    fn cell_of(&self, position: &Position) -> usize {
+        self.document
+            .row(position.y)
+            .map_or(position.x, |row| row.cell_of(position.x))
+    }
+
+    /// Returns the range of graphemes of row `y` that lie inside `block`.
+    fn block_range(&self, block: &Block, y: usize) -> Option<(usize, usize)> {
+        let row = self.document.row(y)?;
+        let start = row.index_at_cell(block.left).min(row.len());
+        let end = row.index_at_cell(block.right).min(row.len());
+        Some((start, end))
+    }
+
+    /// Leaves an empty block at display cell `cell` to type into.
+    fn collapse_block(&mut self, cell: usize) {
+        let index_at_cell = |editor: &Self, y: usize| {
+            editor
+                .document
+                .row(y)
+                .map_or(0, |row| row.index_at_cell(cell))
+        };
+
+        if let Some(anchor) = self.selection_anchor.clone() {
+            self.selection_anchor = Some(Position {
+                x: index_at_cell(self, anchor.y),
+                y: anchor.y,
+            });
+        }
+        self.cursor_position.x = index_at_cell(self, self.cursor_position.y);
+    }
+
+    fn block_text(&self, block: &Block) -> String {
+        (block.top..=block.bottom)
+            .filter_map(|y| {
+                let (start, end) = self.block_range(block, y)?;
+                Some(self.document.row(y)?.substring(start, end))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn delete_block(&mut self, block: &Block) {
+        self.document.begin_edit_group();
+        for y in block.top..=block.bottom {
+            if let Some((start, end)) = self.block_range(block, y) {
+                self.document
+                    .delete_range(&Position { x: start, y }, &Position { x: end, y });
+            }
+        }
+        self.document.end_edit_group();
+        self.collapse_block(block.left);
+    }
+
+    /// Deletes the grapheme before or after an empty block on every row.
+    fn delete_in_block(&mut self, block: &Block, backward: bool) {
+        self.document.begin_edit_group();
+        for y in block.top..=block.bottom {
+            let Some((x, _)) = self.block_range(block, y) else {
+                continue;
+            };
+            let row_len = self.document.row(y).map_or(0, |row| row.len());
+            let at = if backward { x.checked_sub(1) } else { Some(x) };
+            if let Some(at) = at.filter(|at| *at < row_len) {
+                self.document
+                    .delete_range(&Position { x: at, y }, &Position { x: at + 1, y });
+            }
+        }
+        self.document.end_edit_group();
+
+        let left = if backward {
+            self.document.row(self.cursor_position.y).map_or(0, |row| {
+                let x = row.index_at_cell(block.left);
+                row.cell_of(x.saturating_sub(1))
+            })
+        } else {
+            block.left
+        };
+        self.collapse_block(left);
+    }
+
+    /// Pads row `y` with spaces if it doesn't reach `cell`.
+    fn insert_at_cell(&mut self, y: usize, cell: usize, text: &str) {
+        while y >= self.document.len() {
+            let end = Position {
+                x: 0,
+                y: self.document.len(),
+            };
+            self.document.insert_newline(&end);
+        }
+
+        let (x, len) = self
+            .document
+            .row(y)
+            .map_or((0, 0), |row| (row.index_at_cell(cell), row.len()));
+        let padding = " ".repeat(x.saturating_sub(len));
+        self.document
+            .insert_text(&Position { x: x.min(len), y }, &format!("{padding}{text}"));
+    }
+
+    fn insert_into_block(&mut self, block: &Block, text: &str) {
+        self.document.begin_edit_group();
+        if block.right > block.left {
+            self.delete_block(block);
+        }
+        for y in block.top..=block.bottom {
+            self.insert_at_cell(y, block.left, text);
+        }
+        self.document.end_edit_group();
+
+        let width: usize = text.graphemes(true).map(Row::grapheme_width).sum();
+        self.collapse_block(block.left + width);
+    }
+
+    /// Pastes the rows of `text` as a rectangle at the cursor.
+    fn paste_block(&mut self, text: &str) {
+        let cell = self.cell_of(&self.cursor_position);
+        let top = self.cursor_position.y;
+
+        self.document.begin_edit_group();
+        for (offset, line) in text.split('\n').enumerate() {
+            self.insert_at_cell(top + offset, cell, line);
+        }
+        self.document.end_edit_group();
+
+        self.selection_anchor = None;
+        self.cursor_position.y = top;
+        self.cursor_position.x = self
+            .document
+            .row(top)
+            .map_or(0, |row| row.index_at_cell(cell));
+    }
+
+    fn toggle_block_selection(&mut self) {
+        self.block_selection = !self.block_selection;
+        let state = if self.block_selection { "on" } else { "off" };
+        self.status_message = StatusMessage::from(format!("Block selection {state}."));
+    }
+
+    fn edit_at_cursors<F>(&mut self, edit: F)
+    where
+        F: FnOnce(&mut Document, &mut [Position]),
+    {
+        self.selection_anchor = None;
+
+        let mut cursors = vec![self.cursor_position.clone()];
+        cursors.append(&mut self.extra_cursors);
+        edit(&mut self.document, &mut cursors);
+
+        self.cursor_position = cursors.remove(0);
+        self.set_extra_cursors(cursors);
+    }
+
+    /// Drops the extra cursors that ended up on top of another one.
+    fn set_extra_cursors(&mut self, cursors: Vec<Position>) {
+        self.extra_cursors.clear();
+        for cursor in cursors {
+            if cursor != self.cursor_position && !self.extra_cursors.contains(&cursor) {
+                self.extra_cursors.push(cursor);
+            }
+        }
+    }
+
+    fn add_cursor_vertically(&mut self, key: KeyCode) {
+        let rows = self
+            .extra_cursors
+            .iter()
+            .chain(iter::once(&self.cursor_position))
+            .map(|cursor| cursor.y);
+
+        let y = if key == KeyCode::Up {
+            rows.min().and_then(|y| y.checked_sub(1))
+        } else {
+            rows.max()
+                .map(|y| y + 1)
+                .filter(|y| *y < self.document.len())
+        };
+
+        if let Some(y) = y {
+            let width = self.document.row(y).map_or(0, |row| row.len());
+            let x = self.cursor_position.x.min(width);
+
+            self.selection_anchor = None;
+            let mut cursors = self.extra_cursors.clone();
+            cursors.push(Position { x, y });
+            self.set_extra_cursors(cursors);
+        }
+    }
+
+    /// Wraps around at the end of the document.
+    fn add_cursor_at_next_occurrence(&mut self) {
+        let primary = self.cursor_position.clone();
+        let Some((word_start, word_end)) = self
+            .document
+            .row(primary.y)
+            .and_then(|row| row.word_at(primary.x))
+        else {
+            self.status_message = StatusMessage::from("No word under the cursor.".to_string());
+            return;
+        };
+
+        let word = self.document.line(primary.y).unwrap_or_default();
+        let word: String = word
+            .graphemes(true)
+            .skip(word_start)
+            .take(word_end - word_start)
+            .collect();
+        let options = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let Ok(query) = Query::new(&word, options) else {
+            return;
+        };
+        let offset = primary.x - word_start;
+
+        let last = self.extra_cursors.last().unwrap_or(&primary);
+        let start = Position {
+            x: last.x - offset.min(last.x) + (word_end - word_start),
+            y: last.y,
+        };
+        let found = self
+            .document
+            .find_wrapping(&query, &start, SearchDirection::Forward)
+            .map(|(found, _, _)| found);
+
+        let cursor = found.map(|found| Position {
+            x: found.x + offset,
+            y: found.y,
+        });
+        match cursor {
+            Some(cursor) if cursor != primary && !self.extra_cursors.contains(&cursor) => {
+                self.selection_anchor = None;
+                self.extra_cursors.push(cursor);
+            }
+            _ => {
+                self.status_message = StatusMessage::from("No more occurrences.".to_string());
+            }
+        }
+    }
+
+    fn process_control_key(&mut self, ch: char) -> Result<(), std::io::Error> {
+        if matches!(ch.to_ascii_lowercase(), 'r' | 'z' | 'y' | 'x' | 'v') && self.refuse_read_only()
+        {
+            return Ok(());
+        }
+        match ch {
+            'q' | 'Q' => self.quit()?,
+            's' | 'S' => self.save().unwrap_or(()),
+            'f' | 'F' => self.search(),
+            'r' | 'R' => self.replace()?,
+            'z' | 'Z' => self.undo(),
+            'y' | 'Y' => self.redo(),
+            'c' | 'C' => self.copy(),
+            'x' | 'X' => self.cut(),
+            'v' | 'V' => self.paste(),
+            'd' | 'D' => self.add_cursor_at_next_occurrence(),
+            'b' | 'B' => self.toggle_block_selection(),
+            'g' | 'G' => self.go_to()?,
+            'e' | 'E' => self.pick_buffer()?,
+            'w' | 'W' => self.pane_command()?,
+            't' | 'T' => self.toggle_explorer(),
+            'o' | 'O' => self.find_file()?,
+            'p' | 'P' => self.project_search()?,
+            'k' | 'K' => self.build()?,
+            'l' | 'L' => self.pick_quickfix()?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn process_movement(&mut self, key_event: KeyEvent) {
+        self.document.seal_history();
+        if key_event.modifiers.contains(KeyModifiers::SHIFT) {
+            if key_event.modifiers.contains(KeyModifiers::ALT) {
+                self.block_selection = true;
+            }
+            self.extra_cursors.clear();
+            if self.selection_anchor.is_none() {
+                self.selection_anchor = Some(self.cursor_position.clone());
+            }
+        } else {
+            self.selection_anchor = None;
+        }
+        self.move_cursor(key_event.code);
+
+        let cursors = self
+            .extra_cursors
+            .iter()
+            .map(|cursor| self.moved(cursor, key_event.code))
+            .collect();
+        self.set_extra_cursors(cursors);
+    }
+
+    fn process_edit(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(ch) => {
+                self.delete_selection();
+                self.document.insert(&self.cursor_position, ch);
+                self.move_cursor(KeyCode::Right);
+            }
+            KeyCode::Delete | KeyCode::Backspace if self.selection().is_some() => {
+                self.delete_selection();
+            }
+            KeyCode::Delete => self.document.delete(&self.cursor_position),
+            KeyCode::Backspace if (self.cursor_position.x > 0 || self.cursor_position.y > 0) => {
+                self.move_cursor(KeyCode::Left);
+                self.document.delete(&self.cursor_position);
+            }
+            KeyCode::Enter => {
+                self.delete_selection();
+                self.document.insert_newline(&self.cursor_position);
+                self.move_cursor(KeyCode::Right);
+            }
+            KeyCode::Tab => {
+                self.delete_selection();
+                for _ in 0..self.args.tab_width {
+                    self.document.insert(&self.cursor_position, ' ');
+                    self.move_cursor(KeyCode::Right);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn process_cursors_edit(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(ch) => {
+                self.edit_at_cursors(|document, cursors| {
+                    document.insert_at_cursors(cursors, &ch.to_string());
+                });
+            }
+            KeyCode::Enter => {
+                self.edit_at_cursors(|document, cursors| {
+                    document.insert_at_cursors(cursors, "\n");
+                });
+            }
+            KeyCode::Tab => {
+                let spaces = " ".repeat(self.args.tab_width);
+                self.edit_at_cursors(|document, cursors| {
+                    document.insert_at_cursors(cursors, &spaces);
+                });
+            }
+            KeyCode::Delete => self.edit_at_cursors(Document::delete_at_cursors),
+            KeyCode::Backspace => self.edit_at_cursors(Document::backspace_at_cursors),
+            _ => (),
+        }
+    }
+
+    fn process_block_edit(&mut self, block: &Block, code: KeyCode) {
+        match code {
+            KeyCode::Char(ch) => self.insert_into_block(block, &ch.to_string()),
+            KeyCode::Tab => self.insert_into_block(block, &" ".repeat(self.args.tab_width)),
+            KeyCode::Delete | KeyCode::Backspace if block.right > block.left => {
+                self.delete_block(block);
+            }
+            KeyCode::Delete | KeyCode::Backspace => {
+                self.delete_in_block(block, code == KeyCode::Backspace);
+            }
+            KeyCode::Enter => {
+                self.selection_anchor = None;
+                self.process_edit(code);
+            }
+            _ => (),
+        }
+    }
+
+    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
+        let key_event = loop {
+            // While the explorer is shown, the files are checked for changes
+            // whenever no key comes for a while.
+            // A running build is checked for its output the same way.
+            let timeout = if self.running_build.is_some() {
+                Some(BUILD_POLL)
+            } else if self.explorer.is_some() {
+                Some(EXPLORER_POLL)
+            } else {
+                None
+            };
+            let event = if let Some(timeout) = timeout {
+                let event =
+                    Terminal::poll_event(timeout, |width, height| self.resize(width, height))?;
+                let Some(event) = event else {
+                    if self.finish_build() {
+                        return Ok(());
+                    }
+                    if let Some(explorer) = self
+                        .explorer
+                        .as_mut()
+                        .filter(|explorer| explorer.has_changed())
+                    {
+                        explorer.refresh();
+                        return Ok(());
+                    }
+                    continue;
+                };
+                event
+            } else {
+                Terminal::read_event(|width, height| self.resize(width, height))?
+            };
+            match event {
+                Event::Key(key_event) => break key_event,
+                Event::Mouse(mouse_event) if self.process_mouse(mouse_event) => return Ok(()),
+                _ => (),
+            }
+        };
+        if self.explorer_focused {
+            return self.process_explorer_key(key_event);
+        }
+
+        match key_event.code {
+            KeyCode::Char(ch) if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.process_control_key(ch)?;
+            }
+            KeyCode::Up | KeyCode::Down if key_event.modifiers == KeyModifiers::ALT => {
+                self.add_cursor_vertically(key_event.code);
+            }
+            KeyCode::PageDown if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.cycle_buffer(true);
+            }
+            KeyCode::PageUp if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.cycle_buffer(false);
+            }
+            KeyCode::Char('.') if key_event.modifiers == KeyModifiers::ALT => {
+                self.cycle_buffer(true);
+            }
+            KeyCode::Char(',') if key_event.modifiers == KeyModifiers::ALT => {
+                self.cycle_buffer(false);
+            }
+            KeyCode::Char('n' | 'N') if key_event.modifiers == KeyModifiers::ALT => {
+                self.step_quickfix(true);
+            }
+            KeyCode::Char('p' | 'P') if key_event.modifiers == KeyModifiers::ALT => {
+                self.step_quickfix(false);
+            }
+            KeyCode::Esc => {
+                self.selection_anchor = None;
+                self.block_selection = false;
+                self.extra_cursors.clear();
+                self.search_query = None;
+            }
+            KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::PageDown
+            | KeyCode::PageUp
+            | KeyCode::Home
+            | KeyCode::End => self.process_movement(key_event),
+            KeyCode::Char(_)
+            | KeyCode::Enter
+            | KeyCode::Tab
+            | KeyCode::Delete
+            | KeyCode::Backspace
+                if self.refuse_read_only() => {}
+            code => {
+                if let Some(block) = self.block() {
+                    self.process_block_edit(&block, code);
+                } else if self.extra_cursors.is_empty() {
+                    self.process_edit(code);
+                } else {
+                    self.process_cursors_edit(code);
+                }
+            }
+        }
+
+        self.scroll();
+
+        if self.quit_times < QUIT_TIMES {
+            self.quit_times = QUIT_TIMES;
+            self.status_message = StatusMessage::from(String::new());
+        }
+        Ok(())
+    }
+
+    fn refuse_read_only(&mut self) -> bool {
+        if self.document.is_read_only() {
+            self.status_message = StatusMessage::from("Buffer is read-only.".to_string());
+        }
+        self.document.is_read_only()
+    }
+
+    fn pane_rects(&self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        self.layout.arrange(self.panes_area())
+    }
+
+    fn panes_area(&self) -> Rect {
+        let screen = self.screen();
+        let explorer_width = if self.explorer.is_some() {
+            EXPLORER_WIDTH.min(screen.width / 2) + 1
+        } else {
+            0
+        };
+        Rect {
+            x: screen.x + explorer_width,
+            width: screen.width.saturating_sub(explorer_width),
+            ..screen
+        }
+    }
+
+    fn explorer_rect(&self) -> Rect {
+        let screen = self.screen();
+        Rect {
+            width: self.panes_area().x.saturating_sub(screen.x + 1),
+            ..screen
+        }
+    }
+
+    fn screen(&self) -> Rect {
+        let size = self.terminal.get_size();
+        Rect {
+            x: 0,
+            y: usize::from(self.terminal.has_tab_line()),
+            width: size.width as usize,
+            height: size.height as usize + 1,
+        }
+    }
+
+    fn message_bar_row(&self) -> usize {
+        let screen = self.screen();
+        screen.y + screen.height
+    }
+
+    fn focused_rect(&self) -> Rect {
+        let (panes, _) = self.pane_rects();
+        panes
+            .into_iter()
+            .find(|(pane, _)| *pane == self.focused)
+            .map_or_else(|| self.panes_area(), |(_, rect)| rect)
+    }
+
+    fn text_area(&self) -> Rect {
+        let rect = self.focused_rect();
+        Rect {
+            y: rect.y + 1,
+            height: rect.height.saturating_sub(1),
+            ..rect
+        }
+    }
+
+    /// Reads the key after Ctrl-W and runs the pane command it stands for.
+    fn pane_command(&mut self) -> Result<(), std::io::Error> {
+        self.status_message = StatusMessage::from(
+            "Pane: s/v = split, arrows/hjkl/w = focus, +/-/</> = resize, c = close".to_string(),
+        );
+        self.refresh_screen()?;
+        let key_event = self.read_key()?;
+        self.status_message = StatusMessage::from(String::new());
+
+        match key_event.code {
+            KeyCode::Char('s' | 'S') => self.split_pane(SplitDirection::Horizontal),
+            KeyCode::Char('v' | 'V') => self.split_pane(SplitDirection::Vertical),
+            KeyCode::Char('c' | 'C' | 'q' | 'Q') => self.close_pane(),
+            KeyCode::Char('w' | 'W') => {
+                let (panes, _) = self.pane_rects();
+                let position = panes
+                    .iter()
+                    .position(|(pane, _)| *pane == self.focused)
+                    .unwrap_or(0);
+                let (next, _) = panes[(position + 1) % panes.len()];
+                self.focus_pane(next);
+            }
+            KeyCode::Char('+') => self.resize_pane(SplitDirection::Horizontal, 1),
+            KeyCode::Char('-') => self.resize_pane(SplitDirection::Horizontal, -1),
+            KeyCode::Char('>') => self.resize_pane(SplitDirection::Vertical, 1),
+            KeyCode::Char('<') => self.resize_pane(SplitDirection::Vertical, -1),
+            KeyCode::Char('h') => self.focus_towards(KeyCode::Left),
+            KeyCode::Char('j') => self.focus_towards(KeyCode::Down),
+            KeyCode::Char('k') => self.focus_towards(KeyCode::Up),
+            KeyCode::Char('l') => self.focus_towards(KeyCode::Right),
+            code @ (KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down) => {
+                self.focus_towards(code);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// The focus stays in the top or left half.
+    fn split_pane(&mut self, direction: SplitDirection) {
+        if !layout::can_split(self.focused_rect(), direction) {
+            self.status_message = StatusMessage::from("Not enough room to split.".to_string());
+            return;
+        }
+        self.panes.push(Pane {
+            buffer: self.buffer_index,
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone(),
+        });
+        self.layout
+            .split(self.focused, self.panes.len() - 1, direction);
+        self.scroll();
+    }
+
+    fn close_pane(&mut self) {
+        let Some(heir) = self.layout.remove(self.focused) else {
+            self.status_message = StatusMessage::from("Can't close the only pane.".to_string());
+            return;
+        };
+        self.panes.remove(self.focused);
+        self.load_pane(heir);
+    }
+
+    fn resize_pane(&mut self, direction: SplitDirection, delta: isize) {
+        let area = self.panes_area();
+        if self.layout.resize(self.focused, direction, delta, area) {
+            self.scroll();
+        } else {
+            self.status_message = StatusMessage::from("No border to move that way.".to_string());
+        }
+    }
+
+    fn focus_towards(&mut self, key: KeyCode) {
+        let (panes, _) = self.pane_rects();
+        let area = self.text_area();
+        let along = match key {
+            KeyCode::Left | KeyCode::Right => {
+                area.y + self.cursor_position.y.saturating_sub(self.offset.y)
+            }
+            _ => area.x + self.cursor_position.x.saturating_sub(self.offset.x),
+        };
+        if let Some(pane) = layout::neighbour(&panes, self.focused, key, along) {
+            self.focus_pane(pane);
+        }
+    }
+
+    fn focus_pane(&mut self, index: usize) {
+        if index == self.focused {
+            return;
+        }
+        let pane = &mut self.panes[self.focused];
+        pane.buffer = self.buffer_index;
+        pane.cursor_position.clone_from(&self.cursor_position);
+        pane.offset.clone_from(&self.offset);
+        self.load_pane(index);
+    }
+
+    fn load_pane(&mut self, index: usize) {
+        let Pane {
+            buffer,
+            cursor_position,
+            offset,
+        } = &self.panes[index];
+        let (buffer, cursor_position, offset) = (*buffer, cursor_position.clone(), offset.clone());
+
+        self.focused = index;
+        self.switch_buffer(buffer);
+        self.selection_anchor = None;
+        self.search_match = None;
+
+        // Edits made through another pane may have moved the text away from
+        // under the cursor.
+        self.cursor_position = cursor_position;
+        self.clamp_cursor();
+        self.offset = offset;
+        self.scroll();
+    }
+
+    /// Moves the cursor back into the text after edits made elsewhere.
+    fn clamp_cursor(&mut self) {
+        self.cursor_position = self.document.clamp(&self.cursor_position);
+    }
+
+    fn toggle_explorer(&mut self) {
+        if self.explorer_focused {
+            self.explorer = None;
+            self.explorer_focused = false;
+            self.status_message = StatusMessage::from(String::new());
+        } else {
+            self.explorer.get_or_insert_with(Explorer::default);
+            self.explorer_focused = true;
+            self.status_message = StatusMessage::from(EXPLORER_HELP.to_string());
         }
-        self.search_keyword = None;
+        self.scroll();
     }
 
-    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let key_event: KeyEvent = Terminal::read_key(|width, height| {
-            self.terminal.set_size(width, height);
-            self.scroll();
-            self.refresh_screen()?;
-            Ok(())
-        })?;
+    fn process_explorer_key(&mut self, key_event: KeyEvent) -> Result<(), std::io::Error> {
+        let height = self.explorer_rect().height.saturating_sub(1);
+        let Some(explorer) = self.explorer.as_mut() else {
+            self.explorer_focused = false;
+            return Ok(());
+        };
+        let selected = explorer
+            .selected()
+            .map(|entry| (entry.path.clone(), entry.is_dir));
 
         match key_event.code {
-            KeyCode::Char(ch) => {
-                if key_event.modifiers == KeyModifiers::CONTROL {
-                    match ch {
-                        'q' | 'Q' => self.quit()?,
-                        's' | 'S' => self.save().unwrap_or(()),
-                        'f' | 'F' => self.search(),
-                        _ => (),
-                    }
-                } else {
-                    self.document.insert(&self.cursor_position, ch);
-                    self.move_cursor(KeyCode::Right);
-                }
+            KeyCode::Char('t' | 'T') if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.toggle_explorer();
             }
-            KeyCode::Delete => self.document.delete(&self.cursor_position),
-            KeyCode::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(KeyCode::Left);
-                    self.document.delete(&self.cursor_position);
+            KeyCode::Char('q' | 'Q') if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.quit()?;
+            }
+            _ if key_event.modifiers == KeyModifiers::CONTROL => (),
+            KeyCode::Esc => {
+                self.explorer_focused = false;
+                self.status_message = StatusMessage::from(String::new());
+            }
+            KeyCode::Enter | KeyCode::Right => match selected {
+                Some((_, true)) if key_event.code == KeyCode::Right => explorer.expand(),
+                Some((_, true)) => explorer.toggle(),
+                Some((path, false)) => {
+                    self.status_message = StatusMessage::from(String::new());
+                    self.explorer_focused = false;
+                    self.open_file(&path);
                 }
+                None => (),
+            },
+            KeyCode::Left => explorer.collapse(),
+            KeyCode::Char('a') => self.create_file()?,
+            KeyCode::Char('r') => self.rename_file()?,
+            KeyCode::Char('d') => self.delete_file()?,
+            KeyCode::Char('.') => {
+                let shown = if explorer.toggle_hidden() {
+                    "shown"
+                } else {
+                    "hidden"
+                };
+                self.status_message = StatusMessage::from(format!("Hidden files {shown}."));
             }
-            KeyCode::Enter => {
-                self.document.insert_newline(&self.cursor_position);
-                self.move_cursor(KeyCode::Right);
+            KeyCode::Char('i') => {
+                let shown = if explorer.toggle_ignored() {
+                    "shown"
+                } else {
+                    "hidden"
+                };
+                self.status_message = StatusMessage::from(format!("Ignored files {shown}."));
             }
-            KeyCode::Tab => {
-                for _ in 0..TAB_SIZE {
-                    self.document.insert(&self.cursor_position, ' ');
-                    self.move_cursor(KeyCode::Right);
+            code => explorer.move_selection(code, height),
+        }
+        self.scroll();
+        Ok(())
+    }
+
+    /// A name ending with `/` creates a directory.
+    fn create_file(&mut self) -> Result<(), std::io::Error> {
+        let Some(dir) = self.explorer.as_ref().map(Explorer::target_dir) else {
+            return Ok(());
+        };
+        let shown_dir = if dir.as_os_str().is_empty() {
+            "./".to_string()
+        } else {
+            format!("{}/", dir.display())
+        };
+        let Some(name) = self.prompt(&format!("New file in {shown_dir}: "), None, |_, _, _| {})?
+        else {
+            return Ok(());
+        };
+        let is_dir = name.ends_with('/');
+        let path = dir.join(name.trim_end_matches('/'));
+        if path.exists() {
+            self.status_message =
+                StatusMessage::from(format!("{} already exists.", path.display()));
+            return Ok(());
+        }
+        let kind = if is_dir { "directory" } else { "file" };
+        if !self.confirm(&format!("Create {kind} {}?", path.display()))? {
+            return Ok(());
+        }
+
+        let created = if is_dir {
+            fs::create_dir_all(&path)
+        } else {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                })
+                .map(|_| ())
+        };
+        self.status_message = StatusMessage::from(match created {
+            Ok(()) => format!("Created {}.", path.display()),
+            Err(error) => format!("ERR: Could not create {}: {error}", path.display()),
+        });
+        if let Some(explorer) = self.explorer.as_mut() {
+            explorer.reveal(&path);
+        }
+        Ok(())
+    }
+
+    /// Buffers of the files moved follow them.
+    fn rename_file(&mut self) -> Result<(), std::io::Error> {
+        let Some(path) = self
+            .explorer
+            .as_ref()
+            .and_then(Explorer::selected)
+            .map(|entry| entry.path.clone())
+        else {
+            return Ok(());
+        };
+        let prompt = format!("Rename {} to: ", path.display());
+        let Some(name) = self.prompt(&prompt, None, |_, _, _| {})? else {
+            return Ok(());
+        };
+        let new_path = path.parent().unwrap_or(Path::new("")).join(name);
+        if new_path.exists() {
+            self.status_message =
+                StatusMessage::from(format!("{} already exists.", new_path.display()));
+            return Ok(());
+        }
+        let question = format!("Rename {} to {}?", path.display(), new_path.display());
+        if !self.confirm(&question)? {
+            return Ok(());
+        }
+
+        let old = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        let moved: Vec<(usize, String)> = (0..self.buffer_count())
+            .filter_map(|index| {
+                let file_name = self.buffer_document(index).get_file_name()?;
+                let file = fs::canonicalize(file_name).ok()?;
+                let rest = file.strip_prefix(&old).ok()?;
+                let moved = if rest.as_os_str().is_empty() {
+                    new_path.clone()
+                } else {
+                    new_path.join(rest)
+                };
+                Some((index, moved.to_string_lossy().into_owned()))
+            })
+            .collect();
+
+        match fs::rename(&path, &new_path) {
+            Ok(()) => {
+                History::move_sidecars(&old, &new_path);
+                for (index, file_name) in moved {
+                    self.buffer_document_mut(index).set_file_name(file_name);
                 }
+                self.status_message =
+                    StatusMessage::from(format!("Renamed to {}.", new_path.display()));
             }
-            KeyCode::Up
-            | KeyCode::Down
-            | KeyCode::Left
-            | KeyCode::Right
-            | KeyCode::PageDown
-            | KeyCode::PageUp
-            | KeyCode::Home
-            | KeyCode::End => {
-                self.move_cursor(key_event.code);
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!(
+                    "ERR: Could not rename {}: {error}",
+                    path.display()
+                ));
             }
-            _ => (),
         }
+        if let Some(explorer) = self.explorer.as_mut() {
+            explorer.reveal(&new_path);
+        }
+        Ok(())
+    }
 
-        self.scroll();
+    /// Buffers of deleted files stay open, with their text.
+    fn delete_file(&mut self) -> Result<(), std::io::Error> {
+        let Some((path, is_dir)) = self
+            .explorer
+            .as_ref()
+            .and_then(Explorer::selected)
+            .map(|entry| (entry.path.clone(), entry.is_dir))
+        else {
+            return Ok(());
+        };
+        let question = if is_dir {
+            format!("Delete {} and everything in it?", path.display())
+        } else {
+            format!("Delete {}?", path.display())
+        };
+        if !self.confirm(&question)? {
+            return Ok(());
+        }
 
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
-            self.status_message = StatusMessage::from(String::new());
+        let deleted = if is_dir {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        self.status_message = StatusMessage::from(match deleted {
+            Ok(()) => format!("Deleted {}.", path.display()),
+            Err(error) => format!("ERR: Could not delete {}: {error}", path.display()),
+        });
+        if let Some(explorer) = self.explorer.as_mut() {
+            explorer.refresh();
         }
         Ok(())
     }
 
+    fn confirm(&mut self, question: &str) -> Result<bool, std::io::Error> {
+        let answer = self.prompt(&format!("{question} (y/N): "), None, |_, _, _| {})?;
+        Ok(matches!(answer.as_deref(), Some("y" | "Y")))
+    }
+
+    fn jump_to_location(&mut self, location: Option<(usize, Option<usize>)>) {
+        if let Some((line, column)) = location {
+            self.jump_to(
+                line.saturating_sub(1),
+                column.map(|column| column.saturating_sub(1)),
+            );
+        }
+    }
+
+    /// Counts from zero, clamps to the document and centers the cursor.
+    fn jump_to(&mut self, y: usize, x: Option<usize>) {
+        let y = y.min(self.document.len().saturating_sub(1));
+        let len = self.document.row(y).map_or(0, |row| row.len());
+        self.selection_anchor = None;
+        self.extra_cursors.clear();
+        self.cursor_position = Position {
+            x: x.unwrap_or(0).min(len),
+            y,
+        };
+        self.center();
+    }
+
+    fn center(&mut self) {
+        let height = self.text_area().height;
+        self.offset.y = self.cursor_position.y.saturating_sub(height / 2);
+        self.scroll();
+    }
+
     fn scroll(&mut self) {
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.get_size().width as usize;
-        let height = self.terminal.get_size().height as usize;
-        let mut offset = &mut self.offset;
+        let Rect { width, height, .. } = self.text_area();
+        let offset = &mut self.offset;
 
         if y < offset.y {
             offset.y = y;
@@ -299,20 +2148,68 @@ impl Editor {
         if self.should_quit {
             self.terminal.clear_screen()?;
         } else {
-            self.draw_status_bar()?;
-            print!("{}", self.document.highlighter.plain_text_colors);
-
-            self.draw_rows()?;
+            if self.terminal.has_tab_line() {
+                self.draw_tab_line()?;
+            }
+            if self.picker.is_some() {
+                self.draw_status_bar(self.focused, self.screen())?;
+                self.draw_picker()?;
+            } else {
+                if self.explorer.is_some() {
+                    self.draw_explorer()?;
+                }
+                let (panes, dividers) = self.pane_rects();
+                for (pane, rect) in panes {
+                    self.draw_status_bar(pane, rect)?;
+                    self.draw_rows(pane, rect)?;
+                }
+                self.draw_dividers(&dividers)?;
+            }
             self.draw_message_bar()?;
 
-            self.terminal.move_cursor(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self
-                    .cursor_position
-                    .y
-                    .saturating_sub(self.offset.y)
-                    .saturating_add(1),
-            })?;
+            let picked_row = self
+                .picker
+                .as_mut()
+                .map(|picker| picker.scroll(self.terminal.get_size().height as usize));
+            let explorer_rect = self.explorer_rect();
+            let explorer_row = if self.explorer_focused {
+                self.explorer
+                    .as_mut()
+                    .map(|explorer| explorer.scroll(explorer_rect.height.saturating_sub(1)))
+            } else {
+                None
+            };
+            let cursor = if let Some(x) = self.prompt_cursor {
+                Position {
+                    x,
+                    y: self.message_bar_row(),
+                }
+            } else if let Some(row) = picked_row {
+                Position {
+                    x: 0,
+                    y: self.screen().y + row + 1,
+                }
+            } else if let Some(row) = explorer_row {
+                Position {
+                    x: explorer_rect.x,
+                    y: explorer_rect.y + row + 1,
+                }
+            } else {
+                let area = self.text_area();
+                Position {
+                    x: self
+                        .cursor_position
+                        .x
+                        .saturating_sub(self.offset.x)
+                        .saturating_add(area.x),
+                    y: self
+                        .cursor_position
+                        .y
+                        .saturating_sub(self.offset.y)
+                        .saturating_add(area.y),
+                }
+            };
+            self.terminal.move_cursor(&cursor)?;
         }
 
         self.terminal.cursor_show()?;
@@ -320,8 +2217,12 @@ impl Editor {
     }
 
     fn move_cursor(&mut self, key: KeyCode) {
-        let terminal_height = self.terminal.get_size().height as usize;
-        let Position { mut x, mut y } = self.cursor_position;
+        self.cursor_position = self.moved(&self.cursor_position, key);
+    }
+
+    fn moved(&self, from: &Position, key: KeyCode) -> Position {
+        let terminal_height = self.text_area().height;
+        let Position { mut x, mut y } = *from;
 
         let height = self.document.len();
         let mut width = if let Some(row) = self.document.row(y) {
@@ -334,10 +2235,8 @@ impl Editor {
             KeyCode::Up => {
                 y = y.saturating_sub(1);
             }
-            KeyCode::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
+            KeyCode::Down if y < height => {
+                y = y.saturating_add(1);
             }
             KeyCode::Left => {
                 if x > 0 {
@@ -388,44 +2287,99 @@ impl Editor {
             x = width;
         }
 
-        self.cursor_position = Position { x, y };
+        Position { x, y }
     }
 
-    fn draw_welcome_message(&self) {
-        let mut welcome_message = format!("Lekh editor -- version {}", VERSION);
+    /// Only the focused pane shows its selection, matches and extra cursors.
+    fn draw_rows(&mut self, pane: usize, rect: Rect) -> Result<(), std::io::Error> {
+        let area = Rect {
+            y: rect.y + 1,
+            height: rect.height.saturating_sub(1),
+            ..rect
+        };
 
-        let width = self.terminal.get_size().width as usize;
-        let len = welcome_message.len();
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
+        if pane != self.focused {
+            let Pane { buffer, offset, .. } = &self.panes[pane];
+            let (buffer, offset) = (*buffer, offset.clone());
+            let slot = self.buffer_slot(buffer);
+            let document = match slot {
+                Some(slot) => &mut self.buffers[slot].document,
+                None => &mut self.document,
+            };
+            document.highlight(offset.y.saturating_add(area.height));
+            return draw_text(
+                &mut self.terminal,
+                document,
+                &offset,
+                area,
+                &Marks::default(),
+            );
+        }
 
-        welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
+        let mut marks = Marks {
+            reversed: self
+                .selection()
+                .into_iter()
+                .chain(self.search_match.clone())
+                .collect(),
+            block: self.block(),
+            cursors: self.extra_cursors.clone(),
+            ..Marks::default()
+        };
+        // Every other visible match of the search is underlined.
+        if let Some(query) = &self.search_query {
+            let top = self.offset.y;
+            marks.underlined = self
+                .document
+                .find_all(query, top..top.saturating_add(area.height))
+                .into_iter()
+                .filter(|found| Some(found) != self.search_match.as_ref())
+                .collect();
+        }
 
-        println!("{}\r", welcome_message);
+        self.document
+            .highlight(self.offset.y.saturating_add(area.height));
+        draw_text(
+            &mut self.terminal,
+            &self.document,
+            &self.offset,
+            area,
+            &marks,
+        )
     }
 
-    pub fn draw_row(&self, row: &Row) {
+    fn draw_picker(&mut self) -> Result<(), std::io::Error> {
         let width = self.terminal.get_size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-
-        row.render(start, end, &self.search_keyword);
-    }
+        let height = self.terminal.get_size().height as usize;
+        let Some(picker) = self.picker.as_mut() else {
+            return Ok(());
+        };
+        picker.scroll(height);
 
-    fn draw_rows(&mut self) -> Result<(), std::io::Error> {
-        let height = self.terminal.get_size().height;
+        let top = self.screen().y + 1;
+        print!("{}", self.document.highlighter.plain_text_colors);
         for terminal_row in 0..height {
+            self.terminal.move_cursor(&Position {
+                x: 0,
+                y: top + terminal_row,
+            })?;
             self.terminal.clear_current_line()?;
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
-            } else {
-                print!("~\r\n");
+            let Some(picker) = self.picker.as_ref() else {
+                break;
+            };
+            match picker.line(terminal_row, width) {
+                Some((line, true)) => {
+                    let used: usize = line.graphemes(true).map(Row::grapheme_width).sum();
+                    let padding = width.saturating_sub(used);
+                    print!(
+                        "{}{line}{}{}",
+                        Attribute::Reverse,
+                        " ".repeat(padding),
+                        Attribute::Reset
+                    );
+                }
+                Some((line, false)) => print!("{line}"),
+                None => print!("~"),
             }
         }
 
@@ -433,46 +2387,211 @@ impl Editor {
         Ok(())
     }
 
-    fn draw_status_bar(&mut self) -> Result<(), std::io::Error> {
-        let mut status;
+    fn draw_explorer(&mut self) -> Result<(), std::io::Error> {
+        let rect = self.explorer_rect();
+        let height = rect.height.saturating_sub(1);
+        let focused = self.explorer_focused;
+        let Some(explorer) = self.explorer.as_mut() else {
+            return Ok(());
+        };
+        explorer.scroll(height);
+
+        let mut title: String = format!(" {}/", Explorer::root_name())
+            .chars()
+            .take(rect.width)
+            .collect();
+        title.push_str(&" ".repeat(rect.width.saturating_sub(title.chars().count())));
+        self.terminal.move_cursor(&Position {
+            x: rect.x,
+            y: rect.y,
+        })?;
+        self.terminal.set_fg_color(STATUS_FG_COLOR)?;
+        if focused {
+            self.terminal.set_bg_color(STATUS_BG_COLOR)?;
+        } else {
+            self.terminal.set_bg_color(INACTIVE_STATUS_BG_COLOR)?;
+        }
+        print!("{title}");
+        self.terminal.reset_colors()?;
+
+        print!("{}", self.document.highlighter.plain_text_colors);
+        for terminal_row in 0..height {
+            self.terminal.move_cursor(&Position {
+                x: rect.x,
+                y: rect.y + 1 + terminal_row,
+            })?;
+            let (line, selected) = explorer.line(terminal_row, rect.width).unwrap_or_default();
+            let used: usize = line.graphemes(true).map(Row::grapheme_width).sum();
+            let padding = " ".repeat(rect.width.saturating_sub(used));
+            // The selected entry only stands out while the keys go to the
+            // explorer.
+            if selected && focused {
+                print!("{}{line}{padding}{}", Attribute::Reverse, Attribute::Reset);
+                print!("{}", self.document.highlighter.plain_text_colors);
+            } else {
+                print!("{line}{padding}");
+            }
+        }
+
+        self.terminal.reset_colors()?;
+        for y in rect.y..rect.y + rect.height {
+            self.terminal.move_cursor(&Position {
+                x: rect.x + rect.width,
+                y,
+            })?;
+            print!("\u{2502}");
+        }
+        self.terminal.flush()
+    }
+
+    fn tabs(&self) -> Vec<tab_line::Tab> {
+        let labels: Vec<String> = (0..self.buffer_count())
+            .map(|index| tab_line::label(self.buffer_document(index)))
+            .collect();
+        let width = self.terminal.get_size().width as usize;
+        tab_line::arrange(&labels, self.buffer_index, width)
+    }
+
+    fn draw_tab_line(&mut self) -> Result<(), std::io::Error> {
         let width = self.terminal.get_size().width as usize;
+        let tabs = self.tabs();
+
+        let mut line = String::new();
+        if tabs.first().is_some_and(|tab| tab.buffer > 0) {
+            line.push('<');
+        }
+        let start = tabs.first().map_or(0, |tab| tab.start);
+        line.push_str(&" ".repeat(start.saturating_sub(line.len())));
+        for tab in &tabs {
+            line.push_str(&tab.label);
+        }
+        let used = tabs.last().map_or(0, |tab| tab.start + tab.width);
+        let more = tabs
+            .last()
+            .is_some_and(|tab| tab.buffer + 1 < self.buffer_count());
+        line.push_str(&" ".repeat(width.saturating_sub(used + usize::from(more))));
+        if more {
+            line.push('>');
+        }
+
+        self.terminal.move_cursor(&Position::default())?;
+        self.terminal.set_fg_color(STATUS_BG_COLOR)?;
+        self.terminal.set_bg_color(INACTIVE_STATUS_BG_COLOR)?;
+        print!("{line}");
+
+        if let Some(tab) = tabs.iter().find(|tab| tab.buffer == self.buffer_index) {
+            self.terminal
+                .move_cursor(&Position { x: tab.start, y: 0 })?;
+            self.terminal.set_fg_color(STATUS_FG_COLOR)?;
+            self.terminal.set_bg_color(STATUS_BG_COLOR)?;
+            print!("{}", tab.label);
+        }
+        self.terminal.reset_colors()?;
+        Ok(())
+    }
+
+    /// Returns true if the click was on the tab line.
+    fn process_mouse(&mut self, mouse_event: MouseEvent) -> bool {
+        if !self.terminal.has_tab_line()
+            || mouse_event.row != 0
+            || mouse_event.kind != MouseEventKind::Down(MouseButton::Left)
+        {
+            return false;
+        }
+        let column = mouse_event.column as usize;
+        let tabs = self.tabs();
+        if let Some(tab) = tabs
+            .iter()
+            .find(|tab| (tab.start..tab.start + tab.width).contains(&column))
+        {
+            self.switch_buffer(tab.buffer);
+        } else if tabs.first().is_some_and(|tab| column < tab.start) {
+            self.cycle_buffer(false);
+        } else {
+            self.cycle_buffer(true);
+        }
+        true
+    }
+
+    fn draw_dividers(&mut self, dividers: &[Rect]) -> Result<(), std::io::Error> {
+        self.terminal.reset_colors()?;
+        for divider in dividers {
+            for y in divider.y..divider.y + divider.height {
+                self.terminal.move_cursor(&Position { x: divider.x, y })?;
+                print!("\u{2502}");
+            }
+        }
+        self.terminal.flush()
+    }
+
+    fn draw_status_bar(&mut self, pane: usize, rect: Rect) -> Result<(), std::io::Error> {
+        let focused = pane == self.focused;
+        let (buffer, cursor_y) = if focused {
+            (self.buffer_index, self.cursor_position.y)
+        } else {
+            (self.panes[pane].buffer, self.panes[pane].cursor_position.y)
+        };
+        let document = self.buffer_document(buffer);
+        let width = rect.width;
         let mut file_name = "[No Name]".to_string();
 
-        let modified_indicator = if self.document.is_dirty() { " *" } else { "" };
+        let modified_indicator = if document.is_dirty() { " *" } else { "" };
+        let read_only_indicator = if document.is_read_only() { " [RO]" } else { "" };
+        let block_indicator = if focused && self.block_selection {
+            " [BLOCK]"
+        } else {
+            ""
+        };
+        let buffer_indicator = if self.buffer_count() > 1 {
+            format!(" [{}/{}]", buffer + 1, self.buffer_count())
+        } else {
+            String::new()
+        };
 
-        if let Some(filename) = self.document.get_file_name() {
-            file_name = filename;
-            file_name.truncate(20);
+        if let Some(filename) = document.get_file_name() {
+            file_name = filename.chars().take(20).collect();
         }
 
-        status = format!(
-            "{} - {} lines{}",
+        let mut status = format!(
+            "{} - {} lines{}{}{}{}",
             file_name,
-            self.document.len(),
-            modified_indicator
+            document.len(),
+            modified_indicator,
+            read_only_indicator,
+            block_indicator,
+            buffer_indicator
         );
 
-        let line_indicator = format!(
-            "{}/{}",
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
-        );
+        let line_indicator = format!("{}/{}", cursor_y.saturating_add(1), document.len());
 
-        let len = status.len() + line_indicator.len();
+        let len = status.chars().count() + line_indicator.len();
 
         status.push_str(&" ".repeat(width.saturating_sub(len)));
 
-        status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
+        status = format!("{status}{line_indicator}");
+        status = status.chars().take(width).collect();
 
+        self.terminal.move_cursor(&Position {
+            x: rect.x,
+            y: rect.y,
+        })?;
         self.terminal.set_fg_color(STATUS_FG_COLOR)?;
-        self.terminal.set_bg_color(STATUS_BG_COLOR)?;
-        println!("{}\r", status);
+        if focused {
+            self.terminal.set_bg_color(STATUS_BG_COLOR)?;
+        } else {
+            self.terminal.set_bg_color(INACTIVE_STATUS_BG_COLOR)?;
+        }
+        print!("{status}");
+        self.terminal.reset_colors()?;
 
         Ok(())
     }
 
     fn draw_message_bar(&mut self) -> Result<(), std::io::Error> {
+        self.terminal.move_cursor(&Position {
+            x: 0,
+            y: self.message_bar_row(),
+        })?;
         self.terminal.clear_current_line()?;
         let message = &self.status_message;
 
@@ -482,20 +2601,29 @@ impl Editor {
         let width = self.terminal.get_size().width as usize;
         let mut text: String;
 
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let len = message.text.len();
+        if message.time.elapsed() < Duration::new(5, 0) {
+            // The prompt status is right-aligned and wins over the message
+            // when both don't fit.
+            let status = self.prompt_status.as_deref().unwrap_or_default();
+            let status_width = Row::width(status);
+            let message_text = Row::fit(
+                message.text.graphemes(true),
+                width.saturating_sub(status_width),
+            );
+            let len = Row::width(&message_text) + status_width;
             text = format!(
-                "{}{}",
-                message.text.clone(),
-                &" ".repeat(width.saturating_sub(len))
+                "{}{}{}",
+                message_text,
+                &" ".repeat(width.saturating_sub(len)),
+                status
             );
         } else {
-            let help = "HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit";
+            let help = self.help();
             text = format!("{}{}", help, " ".repeat(width.saturating_sub(help.len())));
+            text.truncate(width);
         }
 
-        text.truncate(width);
-        print!("{}", text);
+        print!("{text}");
 
         self.terminal.reset_colors()?;
         self.terminal.flush()?;
@@ -503,49 +2631,110 @@ impl Editor {
         Ok(())
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    fn help(&self) -> &'static str {
+        if self.explorer_focused {
+            EXPLORER_HELP
+        } else {
+            HELP
+        }
+    }
+
+    fn read_key(&mut self) -> Result<KeyEvent, std::io::Error> {
+        Terminal::read_key(|width, height| self.resize(width, height))
+    }
+
+    fn resize(&mut self, width: u16, height: u16) -> Result<(), std::io::Error> {
+        self.terminal.set_size(width, height);
+        self.scroll();
+        self.refresh_screen()
+    }
+
+    fn prompt<C>(
+        &mut self,
+        prompt: &str,
+        kind: Option<PromptKind>,
+        callback: C,
+    ) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, KeyEvent, &String),
+    {
+        let result = self.read_prompt(prompt, kind, callback)?;
+        Ok(result.filter(|result| !result.is_empty()))
+    }
+
+    /// Like `prompt`, but returns an empty answer instead of cancelling.
+    fn read_prompt<C>(
+        &mut self,
+        prompt: &str,
+        kind: Option<PromptKind>,
+        mut callback: C,
+    ) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, KeyCode, &String),
+        C: FnMut(&mut Self, KeyEvent, &String),
     {
-        let mut result = String::new();
+        let entries: Vec<String> =
+            kind.map_or_else(Vec::new, |kind| self.prompt_history.entries(kind).to_vec());
+        let mut recalled: Option<usize> = None;
+        let mut typed = String::new();
+
+        let mut line = PromptLine::default();
+        let mut cancelled = false;
         loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
+            let width = self.terminal.get_size().width as usize;
+            let status_width = self
+                .prompt_status
+                .as_ref()
+                .map_or(0, |status| Row::width(status) + 1);
+            let prompt_width = Row::width(prompt);
+            let (visible, cursor) = line.view(width.saturating_sub(prompt_width + status_width));
+            self.status_message = StatusMessage::from(format!("{prompt}{visible}"));
+            self.prompt_cursor = Some(prompt_width + cursor);
             self.refresh_screen()?;
 
-            let key_event: KeyEvent = Terminal::read_key(|width, height| {
-                self.terminal.set_size(width, height);
-                self.scroll();
-                self.refresh_screen()?;
-                Ok(())
-            })?;
+            let key_event = self.read_key()?;
 
             match key_event.code {
-                KeyCode::Char(ch) => {
-                    if key_event.modifiers == KeyModifiers::CONTROL {
-                        result.clear();
-                        break;
-                    } else {
-                        result.push(ch);
-                    }
-                }
                 KeyCode::Enter => break,
-                KeyCode::Backspace => {
-                    result.pop();
-                }
                 KeyCode::Esc => {
-                    result.clear();
+                    cancelled = true;
                     break;
                 }
-                _ => (),
+                KeyCode::Char('v') if key_event.modifiers == KeyModifiers::CONTROL => {
+                    line.insert(&self.clipboard.paste());
+                }
+                KeyCode::Up if !entries.is_empty() => {
+                    let index = if let Some(index) = recalled {
+                        index.saturating_sub(1)
+                    } else {
+                        typed.clone_from(line.text());
+                        entries.len() - 1
+                    };
+                    recalled = Some(index);
+                    line.set(&entries[index]);
+                }
+                KeyCode::Down => match recalled {
+                    Some(index) if index + 1 < entries.len() => {
+                        recalled = Some(index + 1);
+                        line.set(&entries[index + 1]);
+                    }
+                    Some(_) => {
+                        recalled = None;
+                        line.set(&typed);
+                    }
+                    None => (),
+                },
+                _ => line.edit(key_event),
             }
-            callback(self, key_event.code, &result);
+            callback(self, key_event, line.text());
         }
+        self.prompt_cursor = None;
+        let result = line.text().clone();
 
-        if self.should_quit == true {
+        if self.should_quit {
             return Ok(None);
         }
 
-        let help = "HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit";
+        let help = self.help();
         let help = format!(
             "{}{}",
             help,
@@ -553,9 +2742,175 @@ impl Editor {
         );
         self.status_message = StatusMessage::from(help);
 
-        if result.is_empty() {
+        if cancelled {
             return Ok(None);
         }
+        if let Some(kind) = kind.filter(|_| !result.is_empty()) {
+            self.prompt_history.add(kind, &result);
+        }
         Ok(Some(result))
     }
 }
+
+fn draw_text(
+    terminal: &mut Terminal,
+    document: &Document,
+    offset: &Position,
+    area: Rect,
+    marks: &Marks,
+) -> Result<(), std::io::Error> {
+    print!("{}", document.highlighter.plain_text_colors);
+
+    for terminal_row in 0..area.height {
+        terminal.move_cursor(&Position {
+            x: area.x,
+            y: area.y + terminal_row,
+        })?;
+        terminal.clear_until_newline()?;
+
+        let index = offset.y.saturating_add(terminal_row);
+        if let Some(row) = document.row(index) {
+            let mut reversed = ranges_in_row(&marks.reversed, index, row.len());
+            if let Some(block) = marks
+                .block
+                .as_ref()
+                .filter(|block| (block.top..=block.bottom).contains(&index))
+            {
+                let start = row.index_at_cell(block.left);
+                let end = row.index_at_cell(block.right).max(start + 1);
+                reversed.push((start, end));
+            }
+            reversed.extend(
+                marks
+                    .cursors
+                    .iter()
+                    .filter(|cursor| cursor.y == index)
+                    .map(|cursor| (cursor.x, cursor.x + 1)),
+            );
+            let underlined = ranges_in_row(&marks.underlined, index, row.len());
+            row.render(
+                offset.x,
+                offset.x.saturating_add(area.width),
+                &reversed,
+                &underlined,
+            );
+        } else if document.is_empty() && terminal_row == area.height / 3 {
+            print!("{}", welcome_message(area.width));
+        } else {
+            print!("~");
+        }
+    }
+
+    terminal.flush()
+}
+
+fn welcome_message(width: usize) -> String {
+    let mut welcome_message = format!("Lekh editor -- version {VERSION}");
+
+    let len = welcome_message.len();
+    let padding = width.saturating_sub(len) / 2;
+    let spaces = " ".repeat(padding.saturating_sub(1));
+
+    welcome_message = format!("~{spaces}{welcome_message}");
+    welcome_message.truncate(width);
+    welcome_message
+}
+
+/// Exits if the theme or syntax asked for doesn't exist.
+fn configure(document: &mut Document, args: &Args) {
+    if let Some(theme) = &args.theme {
+        if !document.highlighter.set_theme(theme) {
+            let mut themes = document.highlighter.theme_names();
+            themes.sort_unstable();
+            eprintln!("lekh: unknown theme '{theme}'");
+            eprintln!("Available themes: {}", themes.join(", "));
+            process::exit(2);
+        }
+    }
+    if let Some(syntax) = &args.syntax {
+        if !document.highlighter.set_syntax(syntax) {
+            eprintln!("lekh: unknown syntax '{syntax}'");
+            process::exit(2);
+        }
+    }
+    document.set_read_only(args.read_only);
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Returns the graphemes of row `index` covered by `ranges`.
+fn ranges_in_row(ranges: &[(Position, Position)], index: usize, len: usize) -> Vec<(usize, usize)> {
+    ranges
+        .iter()
+        .filter(|(start, end)| (start.y..=end.y).contains(&index))
+        .map(|(start, end)| {
+            let from = if index == start.y { start.x } else { 0 };
+            let to = if index == end.y { end.x } else { len };
+            (from, to)
+        })
+        .collect()
+}
+
+/// Lines and columns count from one; lines can be relative or a percentage.
+fn parse_go_to(text: &str, current: usize, len: usize) -> Option<(usize, Option<usize>)> {
+    let last = len.saturating_sub(1);
+    let text = text.trim();
+
+    let (y, x) = if let Some(percentage) = text.strip_suffix('%') {
+        let percentage: usize = percentage.trim().parse().ok()?;
+        (last * percentage.min(100) / 100, None)
+    } else if let Some(offset) = text.strip_prefix('+') {
+        (current.saturating_add(offset.parse().ok()?), None)
+    } else if let Some(offset) = text.strip_prefix('-') {
+        (current.saturating_sub(offset.parse().ok()?), None)
+    } else {
+        let (line, column) = cli::parse_line_column(text)?;
+        (
+            line.saturating_sub(1),
+            column.map(|column| column.saturating_sub(1)),
+        )
+    };
+
+    Some((y.min(last), x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goes_to_lines_and_columns() {
+        assert_eq!(parse_go_to("3", 5, 10), Some((2, None)));
+        assert_eq!(parse_go_to(" 3:7 ", 5, 10), Some((2, Some(6))));
+        assert_eq!(parse_go_to("0", 5, 10), Some((0, None)));
+        assert_eq!(parse_go_to("999", 5, 10), Some((9, None)));
+    }
+
+    #[test]
+    fn goes_to_relative_lines() {
+        assert_eq!(parse_go_to("+2", 5, 10), Some((7, None)));
+        assert_eq!(parse_go_to("-2", 5, 10), Some((3, None)));
+        assert_eq!(parse_go_to("+20", 5, 10), Some((9, None)));
+        assert_eq!(parse_go_to("-10", 5, 10), Some((0, None)));
+    }
+
+    #[test]
+    fn goes_to_a_percentage_of_the_document() {
+        assert_eq!(parse_go_to("50%", 5, 11), Some((5, None)));
+        assert_eq!(parse_go_to("100%", 5, 11), Some((10, None)));
+        assert_eq!(parse_go_to("200%", 5, 11), Some((10, None)));
+        assert_eq!(parse_go_to("100%", 0, 0), Some((0, None)));
+    }
+
+    #[test]
+    fn rejects_incomplete_locations() {
+        for text in ["", "+", "-", "%", "10:", ":3", "x", "+x"] {
+            assert_eq!(parse_go_to(text, 5, 10), None, "{text:?}");
+        }
+    }
+}
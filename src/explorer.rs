@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crossterm::event::KeyCode;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::ignore::{self, Gitignore};
+use crate::picker::ListState;
+use crate::Row;
+
+/// A file or directory shown in the explorer.
+pub struct Entry {
+    /// The path relative to the working directory.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    depth: usize,
+}
+
+/// The file tree shown in the sidebar.
+pub struct Explorer {
+    entries: Vec<Entry>,
+    expanded: HashSet<PathBuf>,
+    /// When each listed directory was last changed.
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+    list: ListState,
+    show_hidden: bool,
+    show_ignored: bool,
+}
+
+impl Default for Explorer {
+    fn default() -> Self {
+        let mut explorer = Self {
+            entries: Vec::new(),
+            expanded: HashSet::new(),
+            modified: HashMap::new(),
+            list: ListState::default(),
+            show_hidden: false,
+            show_ignored: false,
+        };
+        explorer.refresh();
+        explorer
+    }
+}
+
+impl Explorer {
+    pub fn root_name() -> String {
+        env::current_dir()
+            .ok()
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| ".".to_string())
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.list.selected)
+    }
+
+    /// Returns the selected directory, or the one holding the selected file.
+    pub fn target_dir(&self) -> PathBuf {
+        match self.selected() {
+            Some(entry) if entry.is_dir => entry.path.clone(),
+            Some(entry) => entry
+                .path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            None => PathBuf::new(),
+        }
+    }
+
+    /// Keeps the same entry selected if it is still there.
+    pub fn refresh(&mut self) {
+        let selected = self.selected().map(|entry| entry.path.clone());
+        self.entries.clear();
+        self.modified.clear();
+        self.list(Path::new(""), 0, &Gitignore::default());
+        if let Some(path) = selected {
+            self.select(&path);
+        }
+        self.list.selected = self.list.selected.min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.modified
+            .iter()
+            .any(|(dir, modified)| modification_time(dir) != *modified)
+    }
+
+    fn list(&mut self, dir: &Path, depth: usize, gitignore: &Gitignore) {
+        let gitignore = gitignore.enter(dir);
+        self.modified
+            .insert(dir.to_path_buf(), modification_time(dir));
+        let Ok(read_dir) = fs::read_dir(ignore::listed(dir)) else {
+            return;
+        };
+
+        let mut children: Vec<(PathBuf, bool)> = read_dir
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let path = dir.join(&name);
+                let is_dir = fs::metadata(&path).is_ok_and(|metadata| metadata.is_dir());
+                let hidden = name.starts_with('.');
+                let shown = !(is_dir && ignore::is_vcs_directory(&name))
+                    && (self.show_hidden || !hidden)
+                    && (self.show_ignored || !gitignore.is_ignored(&path, is_dir));
+                shown.then_some((path, is_dir))
+            })
+            .collect();
+        // Directories come first, then files, each sorted by name.
+        children.sort_by(|(a, a_is_dir), (b, b_is_dir)| b_is_dir.cmp(a_is_dir).then(a.cmp(b)));
+
+        for (path, is_dir) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            self.entries.push(Entry {
+                path: path.clone(),
+                is_dir,
+                depth,
+            });
+            if expanded {
+                self.list(&path, depth + 1, &gitignore);
+            }
+        }
+    }
+
+    pub fn select(&mut self, path: &Path) {
+        if let Some(index) = self.entries.iter().position(|entry| entry.path == path) {
+            self.list.selected = index;
+        }
+    }
+
+    /// Expands the directories holding `path` and selects it.
+    pub fn reveal(&mut self, path: &Path) {
+        self.expanded.extend(
+            path.ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+        self.refresh();
+        self.select(path);
+    }
+
+    pub fn toggle(&mut self) {
+        if let Some(entry) = self.selected().filter(|entry| entry.is_dir) {
+            let path = entry.path.clone();
+            if !self.expanded.remove(&path) {
+                self.expanded.insert(path);
+            }
+            self.refresh();
+        }
+    }
+
+    pub fn expand(&mut self) {
+        if self
+            .selected()
+            .is_some_and(|entry| entry.is_dir && !self.expanded.contains(&entry.path))
+        {
+            self.toggle();
+        }
+    }
+
+    /// Selects the parent directory if there is nothing to collapse.
+    pub fn collapse(&mut self) {
+        let Some(entry) = self.selected() else {
+            return;
+        };
+        if entry.is_dir && self.expanded.contains(&entry.path) {
+            self.toggle();
+        } else if let Some(parent) = entry.path.parent().map(Path::to_path_buf) {
+            self.select(&parent);
+        }
+    }
+
+    pub fn toggle_hidden(&mut self) -> bool {
+        self.show_hidden = !self.show_hidden;
+        self.refresh();
+        self.show_hidden
+    }
+
+    pub fn toggle_ignored(&mut self) -> bool {
+        self.show_ignored = !self.show_ignored;
+        self.refresh();
+        self.show_ignored
+    }
+
+    pub fn move_selection(&mut self, key: KeyCode, height: usize) {
+        self.list.move_selection(key, height, self.entries.len());
+    }
+
+    pub fn scroll(&mut self, height: usize) -> usize {
+        self.list.scroll(height)
+    }
+
+    /// Returns the entry on screen row `row` and whether it is selected.
+    pub fn line(&self, row: usize, width: usize) -> Option<(String, bool)> {
+        let index = self.list.offset + row;
+        let entry = self.entries.get(index)?;
+
+        let marker = if !entry.is_dir {
+            "  "
+        } else if self.expanded.contains(&entry.path) {
+            "\u{25be} "
+        } else {
+            "\u{25b8} "
+        };
+        let name = entry
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        let slash = if entry.is_dir { "/" } else { "" };
+        let text = format!("{}{marker}{name}{slash}", "  ".repeat(entry.depth));
+
+        let line = Row::fit(text.graphemes(true), width);
+        Some((line, index == self.list.selected))
+    }
+}
+
+fn modification_time(dir: &Path) -> Option<SystemTime> {
+    fs::metadata(ignore::listed(dir))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,234 @@
+use std::mem;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::walk;
+
+/// How long the walk collects files before handing them over.
+const BATCH_TIME: Duration = Duration::from_millis(50);
+
+const MATCH_SCORE: usize = 16;
+/// For a match at the start of the path or of a word in it.
+const BOUNDARY_BONUS: usize = 10;
+/// For a match right after the previous one.
+const CONSECUTIVE_BONUS: usize = 12;
+/// For a match in the file name rather than the directories above it.
+const FILE_NAME_BONUS: usize = 4;
+/// The most a gap between two matches costs.
+const MAX_GAP_PENALTY: usize = 8;
+
+/// Fuzzy matches a query against the files a background walk finds.
+pub struct Finder {
+    receiver: Receiver<Vec<PathBuf>>,
+    files: Vec<String>,
+    /// Score and index in `files` of each match, best first.
+    matches: Vec<(usize, usize)>,
+    query: String,
+    done: bool,
+}
+
+impl Default for Finder {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut batch = Vec::new();
+            let mut sent = Instant::now();
+            walk::walk(|path| {
+                batch.push(path);
+                if sent.elapsed() < BATCH_TIME {
+                    return true;
+                }
+                sent = Instant::now();
+                // The finder is gone if the files can't be sent.
+                sender.send(mem::take(&mut batch)).is_ok()
+            });
+            let _ = sender.send(batch);
+        });
+
+        Self {
+            receiver,
+            files: Vec::new(),
+            matches: Vec::new(),
+            query: String::new(),
+            done: false,
+        }
+    }
+}
+
+impl Finder {
+    /// Returns true if the walk found more files since the last call.
+    pub fn receive(&mut self) -> bool {
+        let first = self.files.len();
+        while !self.done {
+            match self.receiver.try_recv() {
+                Ok(batch) => self
+                    .files
+                    .extend(batch.iter().map(|path| path.to_string_lossy().into_owned())),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.done = true,
+            }
+        }
+        if first == self.files.len() {
+            return false;
+        }
+        self.matches.extend(
+            (first..self.files.len())
+                .filter_map(|index| score(&self.query, &self.files[index]).map(|s| (s, index))),
+        );
+        self.sort();
+        true
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn set_query(&mut self, query: &str) {
+        // Whatever matches the longer query also matches the shorter one, so
+        // only the files that matched before need another look.
+        let candidates: Vec<usize> = if query.starts_with(&self.query) {
+            self.matches.iter().map(|(_, index)| *index).collect()
+        } else {
+            (0..self.files.len()).collect()
+        };
+        self.query = query.to_string();
+        self.matches = candidates
+            .into_iter()
+            .filter_map(|index| score(query, &self.files[index]).map(|s| (s, index)))
+            .collect();
+        self.sort();
+    }
+
+    pub fn matches(&self, limit: usize) -> Vec<String> {
+        self.matches
+            .iter()
+            .take(limit)
+            .map(|(_, index)| self.files[*index].clone())
+            .collect()
+    }
+
+    /// Sorts by score, then shorter paths first.
+    fn sort(&mut self) {
+        let files = &self.files;
+        self.matches.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then(files[*a].len().cmp(&files[*b].len()))
+                .then(files[*a].cmp(&files[*b]))
+        });
+    }
+}
+
+/// Returns None unless the query's characters appear in order, ignoring case.
+pub fn score(query: &str, candidate: &str) -> Option<usize> {
+    let query: Vec<char> = query.chars().map(lowercase).collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+    let chars: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = chars.iter().copied().map(lowercase).collect();
+
+    // Find where the first full match ends, then look back from there for
+    // the latest start, so the matched characters are as close as can be.
+    let mut matched = 0;
+    let end = lower.iter().position(|c| {
+        if *c == query[matched] {
+            matched += 1;
+        }
+        matched == query.len()
+    })?;
+    let mut matched = query.len();
+    let start = (0..=end).rev().find(|i| {
+        if lower[*i] == query[matched - 1] {
+            matched -= 1;
+        }
+        matched == 0
+    })?;
+
+    let file_name_start = chars.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+    let mut score = 0;
+    let mut matched = 0;
+    let mut previous: Option<usize> = None;
+    for i in start..=end {
+        if matched == query.len() || lower[i] != query[matched] {
+            continue;
+        }
+        matched += 1;
+        score += MATCH_SCORE;
+        if i == 0 || is_boundary(chars[i - 1], chars[i]) {
+            score += BOUNDARY_BONUS;
+        }
+        if i >= file_name_start {
+            score += FILE_NAME_BONUS;
+        }
+        match previous {
+            Some(previous) if previous + 1 == i => score += CONSECUTIVE_BONUS,
+            Some(previous) => score = score.saturating_sub((i - previous - 1).min(MAX_GAP_PENALTY)),
+            None => (),
+        }
+        previous = Some(i);
+    }
+    Some(score)
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn is_boundary(before: char, c: char) -> bool {
+    matches!(before, '/' | '\\' | '_' | '-' | '.' | ' ')
+        || (before.is_lowercase() && c.is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked<'a>(query: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+        let mut scored: Vec<_> = candidates
+            .iter()
+            .filter_map(|candidate| Some((score(query, candidate)?, *candidate)))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+        scored.into_iter().map(|(_, candidate)| candidate).collect()
+    }
+
+    #[test]
+    fn needs_every_character_in_order() {
+        assert!(score("mn", "src/main.rs").is_some());
+        assert_eq!(score("nm", "main"), None);
+        assert_eq!(score("xyz", "src/main.rs"), None);
+        assert_eq!(score("MAIN", "src/main.rs"), score("main", "src/main.rs"));
+        assert_eq!(score("", "anything"), Some(0));
+    }
+
+    #[test]
+    fn ranks_a_prefix_above_a_scattered_match() {
+        assert_eq!(
+            ranked("edit", &["e_x_d_i_t.rs", "editor.rs"]),
+            ["editor.rs", "e_x_d_i_t.rs"]
+        );
+        assert_eq!(
+            ranked("row", &["src/arrow.rs", "src/row.rs"]),
+            ["src/row.rs", "src/arrow.rs"]
+        );
+    }
+
+    #[test]
+    fn ranks_matches_in_the_file_name_higher() {
+        assert_eq!(
+            ranked("doc", &["doc/readme.md", "src/document.rs"]),
+            ["src/document.rs", "doc/readme.md"]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::fs;
+use std::path::Path;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::walk;
+use crate::Query;
+
+/// A match found by a project search.
+pub struct Hit {
+    pub file: String,
+    /// The row and column the match starts at, both counted from zero.
+    pub y: usize,
+    pub x: usize,
+    /// The text of the row the match starts on.
+    pub line: String,
+}
+
+impl Hit {
+    /// Describes the match for the results list: where it is and the row
+    /// it is on.
+    pub fn label(&self) -> String {
+        format!(
+            "{}:{}:{}: {}",
+            self.file,
+            self.y + 1,
+            self.x + 1,
+            self.line.trim()
+        )
+    }
+}
+
+/// The matches of the last project search.
+pub struct Results {
+    pub query: Query,
+    pub hits: Vec<Hit>,
+    /// The match that was opened last.
+    pub selected: usize,
+}
+
+impl Results {
+    /// Returns the files with matches, in the order they were found.
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = Vec::new();
+        for hit in &self.hits {
+            if files.last() != Some(&hit.file.as_str()) {
+                files.push(&hit.file);
+            }
+        }
+        files
+    }
+}
+
+/// Searches the files below the working directory, as `walk::walk` lists
+/// them, for `query`. `open_text` returns the text of the files that are
+/// open in the editor, which is searched instead of what is saved. Files
+/// that aren't text are skipped.
+pub fn search<F>(query: &Query, mut open_text: F) -> Vec<Hit>
+where
+    F: FnMut(&Path) -> Option<String>,
+{
+    let mut hits = Vec::new();
+    walk::walk(|path| {
+        let text = open_text(&path)
+            .or_else(|| fs::read_to_string(&path).ok())
+            .filter(|text| !text.contains('\0'));
+        if let Some(text) = text {
+            hits.extend(search_text(query, &path.to_string_lossy(), &text));
+        }
+        true
+    });
+    hits
+}
+
+/// Returns the matches of `query` in `text`, the contents of `file`.
+fn search_text(query: &Query, file: &str, text: &str) -> Vec<Hit> {
+    let mut hits = Vec::new();
+    let mut y = 0;
+    let mut line_start = 0;
+    for found in query.find_all(text, 0..text.len()) {
+        while let Some(newline) = text[line_start..]
+            .find('\n')
+            .map(|index| line_start + index)
+            .filter(|newline| *newline < found.start)
+        {
+            y += 1;
+            line_start = newline + 1;
+        }
+        let line_end = text[line_start..]
+            .find('\n')
+            .map_or(text.len(), |index| line_start + index);
+        hits.push(Hit {
+            file: file.to_string(),
+            y,
+            x: text[line_start..found.start].graphemes(true).count(),
+            line: text[line_start..line_end]
+                .trim_end_matches('\r')
+                .to_string(),
+        });
+    }
+    hits
+}
+
+/// Returns `text` with every match of `query` replaced by `template`, and
+/// how many matches there were.
+pub fn replace_all(query: &Query, text: &str, template: &str) -> (String, usize) {
+    let found = query.find_all(text, 0..text.len());
+    let mut replaced = String::with_capacity(text.len());
+    let mut copied = 0;
+    for range in &found {
+        replaced.push_str(&text[copied..range.start]);
+        replaced.push_str(&query.replacement(text, range.clone(), template));
+        copied = range.end;
+    }
+    replaced.push_str(&text[copied..]);
+    (replaced, found.len())
+}
+
+/// Returns the rows that replacing every match of `query` in `text` by
+/// `template` changes, as the index of the first row, the rows before and
+/// the rows after. Matches that share a row are shown together.
+pub fn changed_rows(query: &Query, text: &str, template: &str) -> Vec<(usize, String, String)> {
+    let row_end = |index: usize| {
+        text[index..]
+            .find('\n')
+            .map_or(text.len(), |end| index + end)
+    };
+
+    // The start and end of the replaced text, and what replaces it.
+    let mut spans: Vec<(usize, usize, String)> = Vec::new();
+    for found in query.find_all(text, 0..text.len()) {
+        let replacement = query.replacement(text, found.clone(), template);
+        match spans.last_mut() {
+            Some((_, end, after)) if found.start <= row_end(*end) => {
+                after.push_str(&text[*end..found.start]);
+                after.push_str(&replacement);
+                *end = found.end;
+            }
+            _ => {
+                let start = text[..found.start]
+                    .rfind('\n')
+                    .map_or(0, |newline| newline + 1);
+                let after = format!("{}{replacement}", &text[start..found.start]);
+                spans.push((start, found.end, after));
+            }
+        }
+    }
+
+    let (mut y, mut counted) = (0, 0);
+    spans
+        .into_iter()
+        .map(|(start, end, mut after)| {
+            y += text[counted..start].matches('\n').count();
+            counted = start;
+            let end_of_row = row_end(end);
+            after.push_str(&text[end..end_of_row]);
+            (y, text[start..end_of_row].to_string(), after)
+        })
+        .collect()
+}
+
+/// Replaces every match of `query` in the file at `path` by `template`,
+/// and returns how many there were.
+pub fn replace_in_file(
+    path: &Path,
+    query: &Query,
+    template: &str,
+) -> Result<usize, std::io::Error> {
+    let text = fs::read_to_string(path)?;
+    let (replaced, count) = replace_all(query, &text, template);
+    if count > 0 {
+        fs::write(path, replaced)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchOptions;
+
+    fn regex(pattern: &str) -> Query {
+        let options = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        Query::new(pattern, options).unwrap()
+    }
+
+    #[test]
+    fn finds_the_rows_and_columns_of_matches() {
+        let query = Query::new("ab", SearchOptions::default()).unwrap();
+        let hits = search_text(&query, "f.txt", "xab ab\r\n\u{e9}ab\n");
+        let found: Vec<_> = hits
+            .iter()
+            .map(|hit| (hit.y, hit.x, hit.line.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [(0, 1, "xab ab"), (0, 4, "xab ab"), (1, 1, "\u{e9}ab")]
+        );
+        assert_eq!(hits[2].label(), "f.txt:2:2: \u{e9}ab");
+    }
+
+    #[test]
+    fn replaces_literal_text() {
+        let query = Query::new("a.b", SearchOptions::default()).unwrap();
+        assert_eq!(
+            replace_all(&query, "a.b axb a.b\n", "$1"),
+            ("$1 axb $1\n".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn replaces_regex_matches_with_their_captures() {
+        let query = regex(r"(\w+)=(\d+)");
+        assert_eq!(
+            replace_all(&query, "a=1, b=22\nc=x\n", "$2=$1"),
+            ("1=a, 22=b\nc=x\n".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn replaces_matches_across_rows() {
+        let query = regex(r"\{\n\s*(\w+)\n\}");
+        let text = "f {\n  x\n} g {\n  y\n}\n";
+        assert_eq!(
+            replace_all(&query, text, "{ $1 }"),
+            ("f { x } g { y }\n".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn shows_the_rows_a_replacement_changes() {
+        let query = regex(r"\{\n\s*(\w+)\n\}");
+        let text = "start\nf {\n  x\n} g;\nmid\nh {\n  y\n}\n";
+        assert_eq!(
+            changed_rows(&query, text, "{ $1 }"),
+            [
+                (1, "f {\n  x\n} g;".to_string(), "f { x } g;".to_string()),
+                (5, "h {\n  y\n}".to_string(), "h { y }".to_string()),
+            ]
+        );
+
+        let query = Query::new("o", SearchOptions::default()).unwrap();
+        assert_eq!(
+            changed_rows(&query, "one two\nzero\n", "0"),
+            [
+                (0, "one two".to_string(), "0ne tw0".to_string()),
+                (1, "zero".to_string(), "zer0".to_string()),
+            ]
+        );
+
+        // Joining a row onto the next one shows them both.
+        let query = Query::new("a\n", SearchOptions::default()).unwrap();
+        assert_eq!(
+            changed_rows(&query, "a\nb\n", ""),
+            [(0, "a\nb".to_string(), "b".to_string())]
+        );
+    }
+}
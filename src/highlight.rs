@@ -1,76 +1,238 @@
 use std::error::Error;
+use std::iter;
 use std::process;
 
+use ropey::Rope;
 use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style, ThemeSet};
-use syntect::parsing::SyntaxSet;
-use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use syntect::highlighting::{self, HighlightIterator, HighlightState, Style, Theme, ThemeSet};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::as_24_bit_terminal_escaped;
 
-use crate::Row;
+const THEME: &str = "base16-ocean.dark";
 
+/// Parser and highlighter state at the start of a line.
+#[derive(Clone, PartialEq, Eq)]
+struct LineState {
+    parse: ParseState,
+    highlight: HighlightState,
+}
+
+impl LineState {
+    fn new(syntax: &SyntaxReference, theme: &Theme) -> Self {
+        let highlighter = highlighting::Highlighter::new(theme);
+        Self {
+            parse: ParseState::new(syntax),
+            highlight: HighlightState::new(&highlighter, ScopeStack::new()),
+        }
+    }
+}
+
+/// Highlights a document line by line, caching the state at the start of
+/// every line so that an edit only re-highlights the lines it affects.
+///
+/// `states` has one more entry than `lines`: the state after the last line,
+/// which lines added at the end of the text start from.
+///
+/// Lines in `stale_from..` still have to be highlighted. Lines in
+/// `stale_from + 1..cached_until` were highlighted from the start state
+/// cached for them, so once the highlighter reaches one of them in the same
+/// state again, everything up to `cached_until` is known to be valid.
 pub struct Highlighter {
     syntax_set: SyntaxSet,
     theme_set: ThemeSet,
-    filename: Option<String>,
+    theme: String,
+    syntax: String,
+    forced_syntax: bool,
+    initial: LineState,
+    states: Vec<Option<LineState>>,
+    lines: Vec<Option<String>>,
+    stale_from: usize,
+    cached_until: usize,
     pub plain_text_colors: String,
 }
 
-impl Highlighter {
-    pub fn default() -> Self {
+impl Default for Highlighter {
+    fn default() -> Self {
         let ss = SyntaxSet::load_defaults_newlines();
         let ts = ThemeSet::load_defaults();
 
         let syntax = ss.find_syntax_plain_text();
-        let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
-        let mut plain_text_colors: String = String::new();
+        let mut h = HighlightLines::new(syntax, &ts.themes[THEME]);
+        let ranges: Vec<(Style, &str)> = if let Ok(ranges) = h.highlight_line(" \n", &ss) {
+            ranges
+        } else {
+            eprintln!("Error: Couldn't highlight the file.\r");
+            process::exit(103);
+        };
 
-        for line in LinesWithEndings::from(" ") {
-            let ranges: Vec<(Style, &str)> = if let Ok(ranges) = h.highlight_line(line, &ss) {
-                ranges
-            } else {
-                eprintln!("Error: Couldn't highlight the file.\r");
-                process::exit(103);
-            };
+        let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+        let plain_text_colors = escaped.trim_end().to_string();
 
-            let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
-            plain_text_colors = escaped.trim_end().to_string();
-            break;
-        }
+        let initial = LineState::new(syntax, &ts.themes[THEME]);
+        let syntax = syntax.name.clone();
 
         Self {
             syntax_set: ss,
             theme_set: ts,
-            filename: None,
+            theme: THEME.to_string(),
+            syntax,
+            forced_syntax: false,
+            initial,
+            states: vec![None],
+            lines: vec![],
+            stale_from: 0,
+            cached_until: 0,
             plain_text_colors,
         }
     }
+}
+
+impl Highlighter {
+    /// Picks the syntax for `filename`, either by its extension or by the
+    /// first line of the file on disk, and drops everything highlighted so far.
+    /// A syntax forced with `set_syntax` is kept.
+    pub fn set_file_name(&mut self, filename: &str) {
+        if self.forced_syntax {
+            return;
+        }
+        let syntax = self
+            .syntax_set
+            .find_syntax_for_file(filename)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
 
-    pub fn set_file_name(&mut self, filename: String) {
-        self.filename = Some(filename);
+        self.syntax = syntax.name.clone();
+        self.restart();
     }
 
-    pub fn highlight_contents(&self, contents: &str) -> Result<Vec<Row>, Box<dyn Error>> {
-        let syntax = match &self.filename {
-            Some(file) => self
-                .syntax_set
-                .find_syntax_for_file(file)?
-                .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text()),
-            None => self.syntax_set.find_syntax_plain_text(),
+    /// Highlights as the language called `name`, or with `name` as its file
+    /// extension, whatever the file name. Returns false if there is no such
+    /// language.
+    pub fn set_syntax(&mut self, name: &str) -> bool {
+        let Some(syntax) = self.syntax_set.find_syntax_by_token(name) else {
+            return false;
         };
+        self.syntax = syntax.name.clone();
+        self.forced_syntax = true;
+        self.restart();
+        true
+    }
 
-        let mut h = HighlightLines::new(syntax, &self.theme_set.themes["base16-ocean.dark"]);
+    /// Switches to the color theme called `name`. Returns false if there is
+    /// no such theme.
+    pub fn set_theme(&mut self, name: &str) -> bool {
+        if !self.theme_set.themes.contains_key(name) {
+            return false;
+        }
+        self.theme = name.to_string();
 
-        let mut res: Vec<Row> = Vec::new();
-        for line in LinesWithEndings::from(contents) {
-            let ranges: Vec<(Style, &str)> = h.highlight_line(line, &self.syntax_set)?;
+        let plain_text = self.syntax_set.find_syntax_plain_text();
+        let mut h = HighlightLines::new(plain_text, &self.theme_set.themes[name]);
+        if let Ok(ranges) = h.highlight_line(" \n", &self.syntax_set) {
             let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+            self.plain_text_colors = escaped.trim_end().to_string();
+        }
+        self.restart();
+        true
+    }
+
+    /// Returns the names of the color themes that can be picked.
+    pub fn theme_names(&self) -> Vec<&str> {
+        self.theme_set.themes.keys().map(String::as_str).collect()
+    }
 
-            res.push(Row::from(
-                &line[..line.len() - 1],
-                &escaped[..escaped.len() - 1],
-            ));
+    /// Starts over with the current syntax and theme.
+    fn restart(&mut self) {
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_name(&self.syntax)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        self.initial = LineState::new(syntax, &self.theme_set.themes[&self.theme]);
+        let len = self.lines.len();
+        self.reset(len);
+    }
+
+    /// Forgets every cached line and prepares the cache for `len` lines.
+    pub fn reset(&mut self, len: usize) {
+        self.states = vec![None; len + 1];
+        self.lines = vec![None; len];
+        self.stale_from = 0;
+        self.cached_until = 0;
+    }
+
+    /// Records that the `old_lines` lines starting at `line` were replaced by
+    /// `new_lines` lines.
+    pub fn edit(&mut self, line: usize, old_lines: usize, new_lines: usize) {
+        let shift = |index: usize| {
+            if index >= line + old_lines {
+                (index + new_lines).saturating_sub(old_lines)
+            } else {
+                index.min(line + new_lines)
+            }
+        };
+
+        let start = line.min(self.lines.len());
+        let end = (line + old_lines).min(self.lines.len());
+        self.lines
+            .splice(start..end, iter::repeat_n(None, new_lines));
+
+        // The state at the start of `line` is unaffected by the edit.
+        let start = (line + 1).min(self.states.len());
+        let end = (line + old_lines).clamp(start, self.states.len());
+        let count = (end - start + new_lines).saturating_sub(old_lines);
+        self.states.splice(start..end, iter::repeat_n(None, count));
+
+        if line < self.stale_from {
+            self.cached_until = shift(self.stale_from);
+            self.stale_from = line;
+        } else {
+            self.cached_until = shift(self.cached_until).min(line);
         }
+    }
+
+    /// Highlights the stale lines before `until`, stopping early as soon as
+    /// the highlighter state converges with the cached one.
+    pub fn update(&mut self, text: &Rope, until: usize) -> Result<(), Box<dyn Error>> {
+        let until = until.min(self.lines.len());
+        let highlighter = highlighting::Highlighter::new(&self.theme_set.themes[&self.theme]);
+
+        while self.stale_from < until {
+            let index = self.stale_from;
+            let mut state = self.states[index]
+                .clone()
+                .unwrap_or_else(|| self.initial.clone());
+
+            let line = text.line(index).to_string();
+            let ops = state.parse.parse_line(&line, &self.syntax_set)?;
+            let ranges: Vec<(Style, &str)> =
+                HighlightIterator::new(&mut state.highlight, &ops[..], &line, &highlighter)
+                    .collect();
+            let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
+            self.lines[index] = Some(escaped.trim_end_matches('\n').to_string());
+
+            let next = index + 1;
+            if next < self.cached_until && self.states[next].as_ref() == Some(&state) {
+                self.stale_from = self.cached_until;
+            } else {
+                self.states[next] = Some(state);
+                self.stale_from = next;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
 
-        Ok(res)
+    /// Returns the highlighted contents of a line, if it was highlighted.
+    ///
+    /// Lines that were never highlighted return `None`, while lines below
+    /// the last edit may still carry colors from before it.
+    pub fn line(&self, index: usize) -> Option<&str> {
+        self.lines.get(index)?.as_deref()
     }
 }
@@ -0,0 +1,515 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use crate::storage;
+
+const SIDECAR_HEADER: &str = "lekh-undo 1";
+/// The oldest undo steps are left out of a sidecar that would be bigger.
+const MAX_SIDECAR_BYTES: usize = 8 << 20;
+
+/// A single change to the document text: `removed` was taken out at char
+/// `index` and `inserted` was put in its place.
+#[derive(Clone)]
+pub struct Edit {
+    pub index: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn insert(index: usize, text: &str) -> Self {
+        Self {
+            index,
+            removed: String::new(),
+            inserted: text.to_string(),
+        }
+    }
+
+    pub fn remove(index: usize, text: &str) -> Self {
+        Self {
+            index,
+            removed: text.to_string(),
+            inserted: String::new(),
+        }
+    }
+
+    fn inverse(&self) -> Self {
+        Self {
+            index: self.index,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+
+    /// Returns true if `next` continues the run of typing or deleting that
+    /// ends with this edit.
+    fn continues_with(&self, next: &Self) -> bool {
+        let is_insert = |edit: &Self| edit.removed.is_empty() && !edit.inserted.is_empty();
+        let is_remove = |edit: &Self| edit.inserted.is_empty() && !edit.removed.is_empty();
+
+        if is_insert(self) && is_insert(next) {
+            !self.inserted.contains('\n')
+                && !next.inserted.contains('\n')
+                && self.index + self.inserted.chars().count() == next.index
+        } else if is_remove(self) && is_remove(next) {
+            next.index == self.index || next.index + next.removed.chars().count() == self.index
+        } else {
+            false
+        }
+    }
+}
+
+/// A group of edits that is undone and redone as one step.
+struct Transaction {
+    edits: Vec<Edit>,
+    revision: usize,
+}
+
+impl Transaction {
+    fn encode(&self) -> Result<Vec<u8>, io::Error> {
+        let mut out: Vec<u8> = Vec::new();
+        writeln!(out, "{} {}", self.revision, self.edits.len())?;
+        for edit in &self.edits {
+            writeln!(
+                out,
+                "{} {} {}",
+                edit.index,
+                edit.removed.len(),
+                edit.inserted.len()
+            )?;
+            out.extend_from_slice(edit.removed.as_bytes());
+            out.extend_from_slice(edit.inserted.as_bytes());
+            out.push(b'\n');
+        }
+        Ok(out)
+    }
+}
+
+/// Undo and redo stacks for a document.
+///
+/// Every transaction gets its own revision number, so the revision of the
+/// transaction on top of the undo stack identifies the state of the text.
+pub struct History {
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+    sealed: bool,
+    group_depth: usize,
+    next_revision: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            sealed: true,
+            group_depth: 0,
+            next_revision: 1,
+        }
+    }
+}
+
+impl History {
+    /// Records an edit that was just applied, grouping it with the previous
+    /// one if both are part of the same run of typing.
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+
+        if !self.sealed {
+            let grouped = self.group_depth > 0;
+            if let Some(transaction) = self.undo.last_mut() {
+                let continues = grouped
+                    || transaction
+                        .edits
+                        .last()
+                        .is_some_and(|last| last.continues_with(&edit));
+                if continues {
+                    transaction.edits.push(edit);
+                    return;
+                }
+            }
+        }
+
+        self.undo.push(Transaction {
+            edits: vec![edit],
+            revision: self.next_revision,
+        });
+        self.next_revision += 1;
+        self.sealed = false;
+    }
+
+    /// Makes the next recorded edit start a new undo step, unless a group
+    /// is open.
+    pub fn seal(&mut self) {
+        if self.group_depth == 0 {
+            self.sealed = true;
+        }
+    }
+
+    /// Makes every edit recorded until the matching `end_group` part of one
+    /// undo step, whether or not the edits are next to each other. Groups
+    /// can be nested.
+    pub fn begin_group(&mut self) {
+        self.seal();
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        self.seal();
+    }
+
+    /// Pops the last transaction and returns the edits that revert it, in the
+    /// order they have to be applied.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        let transaction = self.undo.pop()?;
+        let edits = transaction.edits.iter().rev().map(Edit::inverse).collect();
+
+        self.redo.push(transaction);
+        self.sealed = true;
+        Some(edits)
+    }
+
+    /// Pops the last undone transaction and returns its edits.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let transaction = self.redo.pop()?;
+        let edits = transaction.edits.clone();
+
+        self.undo.push(transaction);
+        self.sealed = true;
+        Some(edits)
+    }
+
+    /// Identifies the current state of the text, `0` being the state the
+    /// history started from.
+    pub fn revision(&self) -> usize {
+        self.undo
+            .last()
+            .map_or(0, |transaction| transaction.revision)
+    }
+
+    /// Writes the history to its sidecar file for `file`, tagged with the
+    /// hash of the contents that were just saved to disk.
+    pub fn persist(&self, file: &Path, content_hash: u64) -> Result<(), io::Error> {
+        let dir = sidecar_dir().ok_or(io::ErrorKind::NotFound)?;
+        self.persist_in(&dir, file, content_hash)
+    }
+
+    fn persist_in(&self, dir: &Path, file: &Path, content_hash: u64) -> Result<(), io::Error> {
+        let sidecar = sidecar_path(dir, file)?;
+        fs::create_dir_all(dir)?;
+        fs::write(sidecar, self.serialize(content_hash, MAX_SIDECAR_BYTES)?)
+    }
+
+    /// Encodes the history, dropping the oldest undo steps, then the
+    /// furthest redo steps, until it fits in `limit` bytes.
+    fn serialize(&self, content_hash: u64, limit: usize) -> Result<Vec<u8>, io::Error> {
+        let encode = |stack: &[Transaction]| -> Result<Vec<Vec<u8>>, io::Error> {
+            stack.iter().map(Transaction::encode).collect()
+        };
+        let undo = encode(&self.undo)?;
+        let redo = encode(&self.redo)?;
+
+        let mut out: Vec<u8> = Vec::new();
+        writeln!(out, "{SIDECAR_HEADER}")?;
+        writeln!(out, "{content_hash:016x}")?;
+        writeln!(out, "{}", self.next_revision)?;
+
+        // The two counts take at most 21 bytes each.
+        let mut size = out.len() + 42 + undo.iter().chain(&redo).map(Vec::len).sum::<usize>();
+        let (mut undo, mut redo) = (&undo[..], &redo[..]);
+        while size > limit {
+            let stack = if undo.is_empty() {
+                &mut redo
+            } else {
+                &mut undo
+            };
+            let Some((oldest, rest)) = stack.split_first() else {
+                break;
+            };
+            size -= oldest.len();
+            *stack = rest;
+        }
+
+        for stack in [undo, redo] {
+            writeln!(out, "{}", stack.len())?;
+            for transaction in stack {
+                out.extend_from_slice(transaction);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Loads the history saved for `file`, as long as it was saved for
+    /// contents hashing to `content_hash`. A sidecar left over from contents
+    /// that were changed outside the editor is deleted.
+    pub fn restore(file: &Path, content_hash: u64) -> Option<Self> {
+        Self::restore_from(&sidecar_dir()?, file, content_hash)
+    }
+
+    fn restore_from(dir: &Path, file: &Path, content_hash: u64) -> Option<Self> {
+        let sidecar = sidecar_path(dir, file).ok()?;
+        let too_big = fs::metadata(&sidecar).ok()?.len() > MAX_SIDECAR_BYTES as u64;
+        let history = if too_big {
+            None
+        } else {
+            Self::parse(&fs::read(&sidecar).ok()?, content_hash)
+        };
+        if history.is_none() {
+            let _ = fs::remove_file(&sidecar);
+        }
+        history
+    }
+
+    /// Moves the sidecar of the file that was at `old`, or the sidecars of
+    /// every file below it if it was a directory, to follow it to `new`.
+    /// `old` is the canonical path from before the move.
+    pub fn move_sidecars(old: &Path, new: &Path) {
+        if let Some(dir) = sidecar_dir() {
+            Self::move_sidecars_in(&dir, old, new);
+        }
+    }
+
+    fn move_sidecars_in(dir: &Path, old: &Path, new: &Path) {
+        let is_dir = fs::symlink_metadata(new).is_ok_and(|metadata| metadata.is_dir());
+        if !is_dir {
+            if let Ok(to) = sidecar_path(dir, new) {
+                let _ = fs::rename(sidecar_for(dir, old), to);
+            }
+            return;
+        }
+        if let Ok(read_dir) = fs::read_dir(new) {
+            for entry in read_dir.filter_map(Result::ok) {
+                Self::move_sidecars_in(dir, &old.join(entry.file_name()), &entry.path());
+            }
+        }
+    }
+
+    fn parse(bytes: &[u8], content_hash: u64) -> Option<Self> {
+        let mut reader = Reader { bytes };
+
+        if reader.line()? != SIDECAR_HEADER
+            || u64::from_str_radix(reader.line()?, 16).ok()? != content_hash
+        {
+            return None;
+        }
+
+        let next_revision = reader.line()?.parse().ok()?;
+        let mut stacks: Vec<Vec<Transaction>> = Vec::new();
+        for _ in 0..2 {
+            let count: usize = reader.line()?.parse().ok()?;
+            let mut stack = Vec::new();
+            for _ in 0..count {
+                let mut fields = reader.line()?.split(' ');
+                let revision = fields.next()?.parse().ok()?;
+                let edit_count: usize = fields.next()?.parse().ok()?;
+
+                let mut edits = Vec::new();
+                for _ in 0..edit_count {
+                    let mut fields = reader.line()?.split(' ');
+                    let index = fields.next()?.parse().ok()?;
+                    let removed_len = fields.next()?.parse().ok()?;
+                    let inserted_len = fields.next()?.parse().ok()?;
+
+                    let removed = reader.take(removed_len)?.to_string();
+                    let inserted = reader.take(inserted_len)?.to_string();
+                    if !reader.line()?.is_empty() {
+                        return None;
+                    }
+                    edits.push(Edit {
+                        index,
+                        removed,
+                        inserted,
+                    });
+                }
+                stack.push(Transaction { edits, revision });
+            }
+            stacks.push(stack);
+        }
+
+        if !reader.bytes.is_empty() {
+            return None;
+        }
+        let redo = stacks.pop()?;
+        let undo = stacks.pop()?;
+        Some(Self {
+            undo,
+            redo,
+            sealed: true,
+            group_depth: 0,
+            next_revision,
+        })
+    }
+}
+
+fn sidecar_dir() -> Option<PathBuf> {
+    Some(storage::cache_dir()?.join("undo"))
+}
+
+/// Sidecar files in `dir` are named after a hash of the canonical path of
+/// the file they belong to.
+fn sidecar_path(dir: &Path, file: &Path) -> Result<PathBuf, io::Error> {
+    Ok(sidecar_for(dir, &fs::canonicalize(file)?))
+}
+
+fn sidecar_for(dir: &Path, canonical: &Path) -> PathBuf {
+    let key = storage::hash([canonical.to_string_lossy().as_bytes()]);
+    dir.join(format!("{key:016x}"))
+}
+
+/// Reads the sidecar format: newline-terminated header lines and strings of
+/// a known length in bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> Option<&'a str> {
+        let end = self.bytes.iter().position(|byte| *byte == b'\n')?;
+        let line = str::from_utf8(&self.bytes[..end]).ok()?;
+        self.bytes = &self.bytes[end + 1..];
+        Some(line)
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a str> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        str::from_utf8(taken).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const HASH: u64 = 0x1234_5678_9abc_def0;
+
+    fn history() -> History {
+        let mut history = History::default();
+        history.record(Edit::insert(0, "hello\n"));
+        history.seal();
+        history.record(Edit::remove(0, "h"));
+        history.record(Edit::insert(0, "J\u{e9}"));
+        history.seal();
+        history.record(Edit::insert(7, "world\n"));
+        history.undo();
+        history
+    }
+
+    fn assert_same(restored: &History, history: &History) {
+        assert_eq!(restored.revision(), history.revision());
+        assert_eq!(restored.next_revision, history.next_revision);
+        for (restored, original) in [
+            (&restored.undo, &history.undo),
+            (&restored.redo, &history.redo),
+        ] {
+            assert_eq!(restored.len(), original.len());
+            for (a, b) in restored.iter().zip(original) {
+                assert_eq!(a.revision, b.revision);
+                assert_eq!(a.edits.len(), b.edits.len());
+                for (a, b) in a.edits.iter().zip(&b.edits) {
+                    assert_eq!(
+                        (a.index, &a.removed, &a.inserted),
+                        (b.index, &b.removed, &b.inserted)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_through_the_sidecar_format() {
+        let history = history();
+        let bytes = history.serialize(HASH, MAX_SIDECAR_BYTES).unwrap();
+        let restored = History::parse(&bytes, HASH).unwrap();
+        assert_same(&restored, &history);
+    }
+
+    #[test]
+    fn rejects_a_sidecar_for_other_contents() {
+        let bytes = history().serialize(HASH, MAX_SIDECAR_BYTES).unwrap();
+        assert!(History::parse(&bytes, HASH + 1).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_and_corrupt_sidecars() {
+        let bytes = history().serialize(HASH, MAX_SIDECAR_BYTES).unwrap();
+        for len in 0..bytes.len() {
+            assert!(History::parse(&bytes[..len], HASH).is_none(), "{len}");
+        }
+
+        let mut extra = bytes.clone();
+        extra.extend_from_slice(b"garbage\n");
+        assert!(History::parse(&extra, HASH).is_none());
+
+        let text = String::from_utf8(bytes).unwrap();
+        let corrupt = text.replacen("0 0 6\n", "0 0 9\n", 1);
+        assert_ne!(corrupt, text);
+        assert!(History::parse(corrupt.as_bytes(), HASH).is_none());
+
+        let mut invalid = text.into_bytes();
+        let at = invalid.iter().position(|byte| *byte == 0xc3).unwrap();
+        invalid[at] = 0xff;
+        assert!(History::parse(&invalid, HASH).is_none());
+    }
+
+    #[test]
+    fn drops_the_oldest_steps_to_fit_the_limit() {
+        let mut history = History::default();
+        for index in 0..100 {
+            history.record(Edit::insert(index * 10, &"x".repeat(10)));
+            history.seal();
+        }
+        let full = history.serialize(HASH, MAX_SIDECAR_BYTES).unwrap();
+        let limit = full.len() / 2;
+        let bytes = history.serialize(HASH, limit).unwrap();
+        assert!(bytes.len() <= limit);
+
+        let restored = History::parse(&bytes, HASH).unwrap();
+        assert!(!restored.undo.is_empty() && restored.undo.len() < history.undo.len());
+        assert_eq!(restored.revision(), history.revision());
+        assert_eq!(
+            restored.undo[0].edits[0].index,
+            history.undo[100 - restored.undo.len()].edits[0].index
+        );
+    }
+
+    #[test]
+    fn persists_restores_and_follows_renames() {
+        let root = env::temp_dir().join(format!("lekh-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("dir")).unwrap();
+        let cache = root.join("cache");
+
+        let file = root.join("dir").join("file.txt");
+        fs::write(&file, "hello\n").unwrap();
+        let history = history();
+        history.persist_in(&cache, &file, HASH).unwrap();
+        assert_same(
+            &History::restore_from(&cache, &file, HASH).unwrap(),
+            &history,
+        );
+
+        let old = fs::canonicalize(root.join("dir")).unwrap();
+        fs::rename(root.join("dir"), root.join("moved")).unwrap();
+        History::move_sidecars_in(&cache, &old, &root.join("moved"));
+        let moved = root.join("moved").join("file.txt");
+        assert_same(
+            &History::restore_from(&cache, &moved, HASH).unwrap(),
+            &history,
+        );
+
+        // A sidecar for contents changed outside the editor is deleted.
+        assert!(History::restore_from(&cache, &moved, HASH + 1).is_none());
+        assert!(History::restore_from(&cache, &moved, HASH).is_none());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use regex::Regex;
+
+/// The directories of version control systems, which are never listed.
+const VCS_DIRECTORIES: [&str; 4] = [".git", ".hg", ".svn", ".jj"];
+
+/// A pattern read from a `.gitignore` file.
+struct Rule {
+    /// The directory holding the `.gitignore` file.
+    base: PathBuf,
+    regex: Regex,
+    /// Matched against the whole path below `base`, not just its last part.
+    anchored: bool,
+    negated: bool,
+    directories_only: bool,
+}
+
+/// The `.gitignore` rules of a directory and the directories above it.
+#[derive(Default, Clone)]
+pub struct Gitignore {
+    rules: Vec<Arc<Rule>>,
+}
+
+impl Gitignore {
+    /// Adds the rules of the `.gitignore` file in `dir`, if there is one.
+    #[must_use]
+    pub fn enter(&self, dir: &Path) -> Self {
+        let mut rules = self.rules.clone();
+        if let Ok(contents) = fs::read_to_string(listed(dir).join(".gitignore")) {
+            rules.extend(
+                contents
+                    .lines()
+                    .filter_map(|line| Rule::parse(line, dir))
+                    .map(Arc::new),
+            );
+        }
+        Self { rules }
+    }
+
+    /// The last rule that matches wins, so `!pattern` can un-ignore a path.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn parse(line: &str, base: &Path) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line),
+        };
+        // `\#` and `\!` start patterns that would otherwise be a comment or
+        // negated. Other escapes are left to `glob_to_regex`.
+        let pattern = if pattern.starts_with("\\#") || pattern.starts_with("\\!") {
+            &pattern[1..]
+        } else {
+            pattern
+        };
+        let (directories_only, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            base: base.to_path_buf(),
+            regex: Regex::new(&format!("^{}$", glob_to_regex(pattern))).ok()?,
+            anchored,
+            negated,
+            directories_only,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.directories_only && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        if self.anchored {
+            self.regex.is_match(&slash_separated(relative))
+        } else {
+            relative
+                .file_name()
+                .is_some_and(|name| self.regex.is_match(&name.to_string_lossy()))
+        }
+    }
+}
+
+pub fn is_vcs_directory(name: &str) -> bool {
+    VCS_DIRECTORIES.contains(&name)
+}
+
+/// The empty path stands for the working directory.
+pub fn listed(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }
+}
+
+fn slash_separated(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `*` and `?` don't match `/`, and `**` matches any number of directories.
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                if let Some(length) = chars[i..].iter().position(|c| *c == ']') {
+                    let class: String = chars[i + 1..i + length].iter().collect();
+                    let class = class
+                        .strip_prefix('!')
+                        .map_or(class.clone(), |rest| format!("^{rest}"));
+                    regex.push('[');
+                    regex.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                    regex.push(']');
+                    i += length + 1;
+                    continue;
+                }
+                regex.push_str("\\[");
+            }
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gitignore(contents: &str) -> Gitignore {
+        Gitignore {
+            rules: contents
+                .lines()
+                .filter_map(|line| Rule::parse(line, Path::new("")))
+                .map(Arc::new)
+                .collect(),
+        }
+    }
+
+    fn ignores(gitignore: &Gitignore, path: &str) -> bool {
+        gitignore.is_ignored(Path::new(path), false)
+    }
+
+    #[test]
+    fn anchors_patterns_with_a_slash() {
+        let rules = gitignore("/target\ndocs/*.html");
+        assert!(ignores(&rules, "target"));
+        assert!(!ignores(&rules, "src/target"));
+        assert!(ignores(&rules, "docs/index.html"));
+        assert!(!ignores(&rules, "docs/api/index.html"));
+        assert!(!ignores(&rules, "index.html"));
+
+        let rules = gitignore("target");
+        assert!(ignores(&rules, "target"));
+        assert!(ignores(&rules, "src/target"));
+    }
+
+    #[test]
+    fn matches_double_stars_anywhere() {
+        let rules = gitignore("**/logs");
+        assert!(ignores(&rules, "logs"));
+        assert!(ignores(&rules, "a/b/logs"));
+        assert!(!ignores(&rules, "a/logs/x"));
+
+        let rules = gitignore("build/**");
+        assert!(ignores(&rules, "build/x"));
+        assert!(ignores(&rules, "build/a/b"));
+        assert!(!ignores(&rules, "build"));
+
+        let rules = gitignore("a/**/b");
+        assert!(ignores(&rules, "a/b"));
+        assert!(ignores(&rules, "a/x/y/b"));
+        assert!(!ignores(&rules, "x/a/b"));
+
+        let rules = gitignore("*.rs");
+        assert!(ignores(&rules, "src/main.rs"));
+        let rules = gitignore("src/*");
+        assert!(ignores(&rules, "src/main.rs"));
+        assert!(!ignores(&rules, "src/a/main.rs"));
+    }
+
+    #[test]
+    fn matches_directories_only_with_a_trailing_slash() {
+        let rules = gitignore("build/");
+        assert!(rules.is_ignored(Path::new("build"), true));
+        assert!(rules.is_ignored(Path::new("src/build"), true));
+        assert!(!rules.is_ignored(Path::new("build"), false));
+    }
+
+    #[test]
+    fn takes_back_earlier_patterns_with_a_bang() {
+        let rules = gitignore("*.log\n!keep.log");
+        assert!(ignores(&rules, "debug.log"));
+        assert!(!ignores(&rules, "keep.log"));
+
+        let rules = gitignore("!keep.log\n*.log");
+        assert!(ignores(&rules, "keep.log"));
+    }
+
+    #[test]
+    fn reads_escaped_special_characters_literally() {
+        let rules = gitignore("# comment\n\\#notes\n\\!important\n\\*.txt");
+        assert!(!ignores(&rules, "# comment"));
+        assert!(ignores(&rules, "#notes"));
+        assert!(ignores(&rules, "!important"));
+        assert!(ignores(&rules, "*.txt"));
+        assert!(!ignores(&rules, "notes.txt"));
+    }
+
+    #[test]
+    fn matches_character_classes() {
+        let rules = gitignore("file[0-9].txt\nlog[!a].txt");
+        assert!(ignores(&rules, "file3.txt"));
+        assert!(!ignores(&rules, "filex.txt"));
+        assert!(ignores(&rules, "logb.txt"));
+        assert!(!ignores(&rules, "loga.txt"));
+    }
+}
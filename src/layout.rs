@@ -0,0 +1,417 @@
+use crossterm::event::KeyCode;
+
+/// A rectangle of terminal cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    /// One pane above the other.
+    Horizontal,
+    /// Panes side by side, with a divider column between them.
+    Vertical,
+}
+
+/// How the screen is divided into panes. Leaves hold the index of a pane,
+/// splits give `ratio` thousandths of their room to the first child.
+pub enum Layout {
+    Pane(usize),
+    Split {
+        direction: SplitDirection,
+        ratio: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+/// The fewest rows a pane can have: its status bar and one row of text.
+const MIN_HEIGHT: usize = 2;
+const MIN_WIDTH: usize = 1;
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self::Pane(0)
+    }
+}
+
+impl Layout {
+    /// Returns where every pane goes in `area`, in order from left to right
+    /// and top to bottom within each split, and the divider columns between
+    /// panes that are side by side.
+    pub fn arrange(&self, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let mut panes = Vec::new();
+        let mut dividers = Vec::new();
+        self.arrange_into(area, &mut panes, &mut dividers);
+        (panes, dividers)
+    }
+
+    fn arrange_into(&self, area: Rect, panes: &mut Vec<(usize, Rect)>, dividers: &mut Vec<Rect>) {
+        match self {
+            Self::Pane(pane) => panes.push((*pane, area)),
+            Self::Split {
+                direction,
+                ratio,
+                first,
+                second,
+            } => {
+                let (first_area, second_area) = split_area(area, *direction, *ratio);
+                if *direction == SplitDirection::Vertical {
+                    dividers.push(Rect {
+                        x: first_area.x + first_area.width,
+                        width: 1,
+                        ..area
+                    });
+                }
+                first.arrange_into(first_area, panes, dividers);
+                second.arrange_into(second_area, panes, dividers);
+            }
+        }
+    }
+
+    /// Splits `pane` in two, with `new_pane` after it.
+    pub fn split(&mut self, pane: usize, new_pane: usize, direction: SplitDirection) {
+        match self {
+            Self::Pane(index) if *index == pane => {
+                *self = Self::Split {
+                    direction,
+                    ratio: 500,
+                    first: Box::new(Self::Pane(pane)),
+                    second: Box::new(Self::Pane(new_pane)),
+                };
+            }
+            Self::Pane(_) => (),
+            Self::Split { first, second, .. } => {
+                first.split(pane, new_pane, direction);
+                second.split(pane, new_pane, direction);
+            }
+        }
+    }
+
+    /// Takes `pane` out, giving its room to its sibling, and renumbers the
+    /// panes after it. Returns the pane that now has the room, or None if
+    /// `pane` is the only one left.
+    pub fn remove(&mut self, pane: usize) -> Option<usize> {
+        let heir = self.remove_pane(pane)?;
+        self.renumber(pane);
+        Some(if heir > pane { heir - 1 } else { heir })
+    }
+
+    fn remove_pane(&mut self, pane: usize) -> Option<usize> {
+        let Self::Split { first, second, .. } = self else {
+            return None;
+        };
+        let sibling = if matches!(**first, Self::Pane(index) if index == pane) {
+            second
+        } else if matches!(**second, Self::Pane(index) if index == pane) {
+            first
+        } else {
+            return first.remove_pane(pane).or_else(|| second.remove_pane(pane));
+        };
+
+        let sibling = std::mem::take(&mut **sibling);
+        *self = sibling;
+        Some(self.first_pane())
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Pane(index) if *index > removed => *index -= 1,
+            Self::Pane(_) => (),
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    fn first_pane(&self) -> usize {
+        match self {
+            Self::Pane(index) => *index,
+            Self::Split { first, .. } => first.first_pane(),
+        }
+    }
+
+    /// Grows `pane` by `delta` cells, or shrinks it if `delta` is negative,
+    /// by moving the nearest border in `direction` around it. Returns false
+    /// if there is no such border.
+    pub fn resize(
+        &mut self,
+        pane: usize,
+        direction: SplitDirection,
+        delta: isize,
+        area: Rect,
+    ) -> bool {
+        let Self::Split {
+            direction: split_direction,
+            ratio,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+        let (first_area, second_area) = split_area(area, *split_direction, *ratio);
+        let in_first = first.contains(pane);
+
+        let resized = if in_first {
+            first.resize(pane, direction, delta, first_area)
+        } else {
+            second.resize(pane, direction, delta, second_area)
+        };
+        if resized || *split_direction != direction {
+            return resized;
+        }
+
+        let total = match direction {
+            SplitDirection::Horizontal => area.height,
+            SplitDirection::Vertical => area.width.saturating_sub(1),
+        };
+        if total == 0 {
+            return false;
+        }
+        let step = (1000 / total).max(1);
+        let change = delta.unsigned_abs() * step;
+        let grow_first = in_first == (delta > 0);
+        *ratio = if grow_first {
+            (*ratio + change).min(1000)
+        } else {
+            ratio.saturating_sub(change)
+        };
+        true
+    }
+
+    fn contains(&self, pane: usize) -> bool {
+        match self {
+            Self::Pane(index) => *index == pane,
+            Self::Split { first, second, .. } => first.contains(pane) || second.contains(pane),
+        }
+    }
+}
+
+/// Divides `area` between the two children of a split.
+fn split_area(area: Rect, direction: SplitDirection, ratio: usize) -> (Rect, Rect) {
+    match direction {
+        SplitDirection::Horizontal => {
+            let height = share(area.height, ratio, MIN_HEIGHT);
+            (
+                Rect { height, ..area },
+                Rect {
+                    y: area.y + height,
+                    height: area.height - height,
+                    ..area
+                },
+            )
+        }
+        SplitDirection::Vertical => {
+            let room = area.width.saturating_sub(1);
+            let width = share(room, ratio, MIN_WIDTH);
+            (
+                Rect { width, ..area },
+                Rect {
+                    x: area.x + width + 1,
+                    width: room - width,
+                    ..area
+                },
+            )
+        }
+    }
+}
+
+/// Returns the first child's part of `total` cells, leaving each child at
+/// least `min` cells if there is room for that.
+fn share(total: usize, ratio: usize, min: usize) -> usize {
+    let first = total * ratio / 1000;
+    if total < 2 * min {
+        first.min(total)
+    } else {
+        first.clamp(min, total - min)
+    }
+}
+
+/// Returns true if `area` is big enough to split in `direction`.
+pub fn can_split(area: Rect, direction: SplitDirection) -> bool {
+    match direction {
+        SplitDirection::Horizontal => area.height >= 2 * MIN_HEIGHT,
+        SplitDirection::Vertical => area.width > 2 * MIN_WIDTH,
+    }
+}
+
+/// Returns the pane next to `from` on the side an arrow `key` points to,
+/// picking the one closest to screen row or column `along` if there are
+/// several.
+pub fn neighbour(
+    panes: &[(usize, Rect)],
+    from: usize,
+    key: KeyCode,
+    along: usize,
+) -> Option<usize> {
+    let (_, rect) = panes.iter().find(|(pane, _)| *pane == from)?;
+
+    let overlaps = |a: usize, a_len: usize, b: usize, b_len: usize| a < b + b_len && b < a + a_len;
+    let distance_along = |start: usize, len: usize| {
+        if along < start {
+            start - along
+        } else {
+            along.saturating_sub(start + len - 1)
+        }
+    };
+
+    panes
+        .iter()
+        .filter(|(pane, _)| *pane != from)
+        .filter_map(|(pane, other)| {
+            let (beside, gap, offset) = match key {
+                KeyCode::Left => (
+                    other.x + other.width <= rect.x,
+                    rect.x.saturating_sub(other.x + other.width),
+                    distance_along(other.y, other.height),
+                ),
+                KeyCode::Right => (
+                    other.x >= rect.x + rect.width,
+                    other.x.saturating_sub(rect.x + rect.width),
+                    distance_along(other.y, other.height),
+                ),
+                KeyCode::Up => (
+                    other.y + other.height <= rect.y,
+                    rect.y.saturating_sub(other.y + other.height),
+                    distance_along(other.x, other.width),
+                ),
+                KeyCode::Down => (
+                    other.y >= rect.y + rect.height,
+                    other.y.saturating_sub(rect.y + rect.height),
+                    distance_along(other.x, other.width),
+                ),
+                _ => return None,
+            };
+            let shares_edge = match key {
+                KeyCode::Left | KeyCode::Right => {
+                    overlaps(rect.y, rect.height, other.y, other.height)
+                }
+                _ => overlaps(rect.x, rect.width, other.x, other.width),
+            };
+            (beside && shares_edge).then_some(((gap, offset), *pane))
+        })
+        .min_by_key(|(key, _)| *key)
+        .map(|(_, pane)| pane)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 80,
+        height: 24,
+    };
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Pane 0 on the left, 1 above 2 on the right.
+    fn nested() -> Layout {
+        let mut layout = Layout::default();
+        layout.split(0, 1, SplitDirection::Vertical);
+        layout.split(1, 2, SplitDirection::Horizontal);
+        layout
+    }
+
+    #[test]
+    fn splits_both_ways() {
+        let mut layout = Layout::default();
+        layout.split(0, 1, SplitDirection::Horizontal);
+        assert_eq!(
+            layout.arrange(SCREEN),
+            (
+                vec![(0, rect(0, 0, 80, 12)), (1, rect(0, 12, 80, 12))],
+                vec![]
+            )
+        );
+
+        assert_eq!(
+            nested().arrange(SCREEN),
+            (
+                vec![
+                    (0, rect(0, 0, 39, 24)),
+                    (1, rect(40, 0, 40, 12)),
+                    (2, rect(40, 12, 40, 12)),
+                ],
+                vec![rect(39, 0, 1, 24)]
+            )
+        );
+    }
+
+    #[test]
+    fn closing_a_pane_gives_its_room_to_the_sibling_subtree() {
+        let mut layout = nested();
+        assert_eq!(layout.remove(0), Some(0));
+        assert_eq!(
+            layout.arrange(SCREEN),
+            (
+                vec![(0, rect(0, 0, 80, 12)), (1, rect(0, 12, 80, 12))],
+                vec![]
+            )
+        );
+
+        assert_eq!(layout.remove(1), Some(0));
+        assert_eq!(layout.remove(0), None);
+        assert_eq!(layout.arrange(SCREEN), (vec![(0, SCREEN)], vec![]));
+    }
+
+    #[test]
+    fn renumbers_the_panes_after_a_closed_one() {
+        let mut layout = nested();
+        assert_eq!(layout.remove(1), Some(1));
+        assert_eq!(
+            layout.arrange(SCREEN),
+            (
+                vec![(0, rect(0, 0, 39, 24)), (1, rect(40, 0, 40, 24))],
+                vec![rect(39, 0, 1, 24)]
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_resized_panes_on_screen() {
+        let mut layout = Layout::default();
+        layout.split(0, 1, SplitDirection::Vertical);
+        assert!(layout.resize(0, SplitDirection::Vertical, 5, SCREEN));
+        let (panes, _) = layout.arrange(SCREEN);
+        assert_eq!(panes[0].1.width, 44);
+
+        assert!(layout.resize(0, SplitDirection::Vertical, 1000, SCREEN));
+        let (panes, _) = layout.arrange(SCREEN);
+        assert_eq!((panes[0].1.width, panes[1].1.width), (78, 1));
+
+        assert!(layout.resize(1, SplitDirection::Vertical, 1000, SCREEN));
+        let (panes, _) = layout.arrange(SCREEN);
+        assert_eq!((panes[0].1.width, panes[1].1.width), (1, 78));
+
+        // There is no border between panes above each other to move.
+        assert!(!layout.resize(0, SplitDirection::Horizontal, 1, SCREEN));
+    }
+
+    #[test]
+    fn finds_neighbours_across_nested_splits() {
+        let (panes, _) = nested().arrange(SCREEN);
+        assert_eq!(neighbour(&panes, 0, KeyCode::Right, 3), Some(1));
+        assert_eq!(neighbour(&panes, 0, KeyCode::Right, 18), Some(2));
+        assert_eq!(neighbour(&panes, 2, KeyCode::Left, 18), Some(0));
+        assert_eq!(neighbour(&panes, 1, KeyCode::Down, 50), Some(2));
+        assert_eq!(neighbour(&panes, 2, KeyCode::Up, 50), Some(1));
+        assert_eq!(neighbour(&panes, 0, KeyCode::Left, 3), None);
+        assert_eq!(neighbour(&panes, 1, KeyCode::Up, 50), None);
+    }
+}
@@ -1,17 +1,51 @@
 use editor::Editor;
 
+pub use buffer::Buffer;
+pub use cli::Args;
+pub use clipboard::Clipboard;
 pub use document::Document;
 pub use editor::Position;
 pub use editor::SearchDirection;
+pub use explorer::Explorer;
+pub use finder::Finder;
 pub use highlight::Highlighter;
+pub use history::Edit;
+pub use history::History;
+pub use layout::Layout;
+pub use layout::Rect;
+pub use layout::SplitDirection;
+pub use picker::Picker;
+pub use prompt_history::PromptHistory;
+pub use prompt_history::PromptKind;
+pub use prompt_line::PromptLine;
+pub use quickfix::Quickfix;
 pub use row::Row;
+pub use search::Query;
+pub use search::SearchOptions;
 pub use terminal::Terminal;
 
+mod buffer;
+mod cli;
+mod clipboard;
 mod document;
 mod editor;
+mod explorer;
+mod finder;
+mod grep;
 mod highlight;
+mod history;
+mod ignore;
+mod layout;
+mod picker;
+mod prompt_history;
+mod prompt_line;
+mod quickfix;
 mod row;
+mod search;
+mod storage;
+mod tab_line;
 mod terminal;
+mod walk;
 
 fn main() {
     Editor::default().run();
@@ -0,0 +1,117 @@
+use crossterm::event::KeyCode;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Row;
+
+/// The selected item of a list and the first one on screen.
+#[derive(Default)]
+pub struct ListState {
+    pub selected: usize,
+    pub offset: usize,
+}
+
+impl ListState {
+    /// Moves the selection for an arrow, page or Home/End key.
+    pub fn move_selection(&mut self, key: KeyCode, height: usize, len: usize) {
+        let last = len.saturating_sub(1);
+        self.selected = match key {
+            KeyCode::Up => self.selected.saturating_sub(1),
+            KeyCode::Down => (self.selected + 1).min(last),
+            KeyCode::PageUp => self.selected.saturating_sub(height),
+            KeyCode::PageDown => (self.selected + height).min(last),
+            KeyCode::Home => 0,
+            KeyCode::End => last,
+            _ => self.selected,
+        };
+    }
+
+    /// Returns the screen row of the selected item once it is in view.
+    pub fn scroll(&mut self, height: usize) -> usize {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height.max(1);
+        }
+        self.selected - self.offset
+    }
+}
+
+/// A list shown in place of the document, to pick one of its items from.
+pub struct Picker {
+    items: Vec<String>,
+    list: ListState,
+}
+
+impl Picker {
+    pub fn new(items: Vec<String>, selected: usize) -> Self {
+        Self {
+            list: ListState {
+                selected: selected.min(items.len().saturating_sub(1)),
+                offset: 0,
+            },
+            items,
+        }
+    }
+
+    /// Keeps the same item selected if it is still there, and selects the
+    /// first one otherwise.
+    pub fn set_items(&mut self, items: Vec<String>) {
+        let selected = self.selected_item();
+        self.list.selected = items
+            .iter()
+            .position(|item| Some(item.as_str()) == selected)
+            .unwrap_or(0);
+        self.items = items;
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        (self.list.selected < self.items.len()).then_some(self.list.selected)
+    }
+
+    pub fn selected_item(&self) -> Option<&str> {
+        self.items.get(self.list.selected).map(String::as_str)
+    }
+
+    pub fn move_selection(&mut self, key: KeyCode, height: usize) {
+        self.list.move_selection(key, height, self.items.len());
+    }
+
+    pub fn scroll(&mut self, height: usize) -> usize {
+        self.list.scroll(height)
+    }
+
+    /// Returns the item on screen row `row` and whether it is selected.
+    pub fn line(&self, row: usize, width: usize) -> Option<(String, bool)> {
+        let index = self.list.offset + row;
+        let item = self.items.get(index)?;
+
+        let line = Row::fit(item.graphemes(true), width);
+        Some((line, index == self.list.selected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn keeps_the_selected_item_when_the_items_change() {
+        let mut picker = Picker::new(items(&["a", "b", "c"]), 0);
+        picker.move_selection(KeyCode::Down, 10);
+        assert_eq!(picker.selected_item(), Some("b"));
+
+        picker.set_items(items(&["x", "c", "b", "a"]));
+        assert_eq!(picker.selected(), Some(2));
+        assert_eq!(picker.selected_item(), Some("b"));
+
+        picker.set_items(items(&["x", "y"]));
+        assert_eq!(picker.selected_item(), Some("x"));
+
+        picker.set_items(Vec::new());
+        assert_eq!(picker.selected(), None);
+    }
+}
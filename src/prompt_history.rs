@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::storage;
+
+const HISTORY_HEADER: &str = "lekh-prompt-history 1";
+const MAX_ENTRIES: usize = 100;
+
+/// The prompts that keep a history of their own.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    Search,
+    Replace,
+    SaveAs,
+    GoTo,
+    Build,
+}
+
+impl PromptKind {
+    const ALL: [Self; 5] = [
+        Self::Search,
+        Self::Replace,
+        Self::SaveAs,
+        Self::GoTo,
+        Self::Build,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Search => "search",
+            Self::Replace => "replace",
+            Self::SaveAs => "save-as",
+            Self::GoTo => "go-to",
+            Self::Build => "build",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// Answers given to earlier prompts, oldest first, kept apart for every
+/// kind of prompt and saved between sessions.
+#[derive(Default)]
+pub struct PromptHistory {
+    entries: HashMap<PromptKind, Vec<String>>,
+}
+
+impl PromptHistory {
+    /// Loads the history saved by earlier sessions, if there is any.
+    pub fn load() -> Self {
+        let mut history = Self::default();
+        let Some(contents) = history_path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return history;
+        };
+
+        let mut lines = contents.lines();
+        if lines.next() != Some(HISTORY_HEADER) {
+            return history;
+        }
+        for line in lines {
+            if let Some((kind, entry)) = line.split_once('\t') {
+                if let Some(kind) = PromptKind::from_name(kind) {
+                    history
+                        .entries
+                        .entry(kind)
+                        .or_default()
+                        .push(unescape(entry));
+                }
+            }
+        }
+        history
+    }
+
+    pub fn entries(&self, kind: PromptKind) -> &[String] {
+        self.entries.get(&kind).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds `entry` as the latest answer to a prompt of `kind`, moving it
+    /// up if it was given before, and saves the history.
+    pub fn add(&mut self, kind: PromptKind, entry: &str) {
+        let entries = self.entries.entry(kind).or_default();
+        entries.retain(|existing| existing != entry);
+        entries.push(entry.to_string());
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+        }
+
+        // Losing the history is not worth bothering the user about.
+        let _ = self.save();
+    }
+
+    fn save(&self) -> Result<(), io::Error> {
+        let path = history_path().ok_or(io::ErrorKind::NotFound)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut out: Vec<u8> = Vec::new();
+        writeln!(out, "{HISTORY_HEADER}")?;
+        for kind in PromptKind::ALL {
+            for entry in self.entries(kind) {
+                writeln!(out, "{}\t{}", kind.name(), escape(entry))?;
+            }
+        }
+
+        fs::write(path, out)
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    Some(storage::state_dir()?.join("prompt-history"))
+}
+
+/// Escapes backslashes and line breaks, so that every entry takes up a
+/// single line of the history file.
+fn escape(entry: &str) -> String {
+    entry
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(entry: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = entry.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('r') => unescaped.push('\r'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,236 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Row;
+
+/// The text typed into a prompt, with a cursor that can be moved around in
+/// it, and the part of it scrolled out of view on the left.
+#[derive(Default)]
+pub struct PromptLine {
+    text: String,
+    cursor: usize,
+    offset: usize,
+}
+
+impl PromptLine {
+    pub fn text(&self) -> &String {
+        &self.text
+    }
+
+    /// Replaces the text and puts the cursor at its end.
+    pub fn set(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.len();
+    }
+
+    /// Applies a line editing key. Other keys are ignored.
+    pub fn edit(&mut self, key_event: KeyEvent) {
+        let word_jump = key_event
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+
+        match key_event.code {
+            KeyCode::Char('w') if key_event.modifiers == KeyModifiers::CONTROL => {
+                let start = self.word_start();
+                self.remove(start, self.cursor);
+            }
+            KeyCode::Char('u') if key_event.modifiers == KeyModifiers::CONTROL => self.set(""),
+            KeyCode::Char(ch)
+                if !key_event
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                self.insert(&ch.to_string());
+            }
+            KeyCode::Left if word_jump => self.cursor = self.word_start(),
+            KeyCode::Right if word_jump => self.cursor = self.word_end(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.len(),
+            KeyCode::Backspace if self.cursor > 0 => self.remove(self.cursor - 1, self.cursor),
+            KeyCode::Delete => self.remove(self.cursor, self.cursor + 1),
+            _ => (),
+        }
+    }
+
+    /// Inserts `text` at the cursor and moves the cursor past it.
+    pub fn insert(&mut self, text: &str) {
+        let index = self.byte_index(self.cursor);
+        self.text.insert_str(index, text);
+        self.cursor += text.graphemes(true).count();
+    }
+
+    /// Returns the part of the line that fits in `width` cells, scrolled so
+    /// that the cursor stays visible, and the cell the cursor is on.
+    pub fn view(&mut self, width: usize) -> (String, usize) {
+        let graphemes: Vec<&str> = self.text.graphemes(true).map(shown_as).collect();
+        let cells = |range: &[&str]| {
+            range
+                .iter()
+                .copied()
+                .map(Row::grapheme_width)
+                .sum::<usize>()
+        };
+
+        // The cursor takes up a cell of its own at the end of the line.
+        self.offset = self.offset.min(self.cursor);
+        while self.offset < self.cursor && cells(&graphemes[self.offset..self.cursor]) >= width {
+            self.offset += 1;
+        }
+
+        let visible = Row::fit(graphemes[self.offset..].iter().copied(), width);
+        (visible, cells(&graphemes[self.offset..self.cursor]))
+    }
+
+    fn len(&self) -> usize {
+        self.text.graphemes(true).count()
+    }
+
+    fn byte_index(&self, index: usize) -> usize {
+        self.text
+            .grapheme_indices(true)
+            .nth(index)
+            .map_or(self.text.len(), |(byte_index, _)| byte_index)
+    }
+
+    fn remove(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len());
+        if start >= end {
+            return;
+        }
+        let range = self.byte_index(start)..self.byte_index(end);
+        self.text.replace_range(range, "");
+        self.cursor = start;
+    }
+
+    /// Returns where the word before the cursor starts, skipping any
+    /// whitespace right before the cursor first.
+    fn word_start(&self) -> usize {
+        let graphemes: Vec<&str> = self.text.graphemes(true).collect();
+        let mut index = self.cursor;
+        while index > 0 && is_space(graphemes[index - 1]) {
+            index -= 1;
+        }
+        while index > 0 && !is_space(graphemes[index - 1]) {
+            index -= 1;
+        }
+        index
+    }
+
+    /// Returns where the word after the cursor ends, skipping any
+    /// whitespace right after the cursor first.
+    fn word_end(&self) -> usize {
+        let graphemes: Vec<&str> = self.text.graphemes(true).collect();
+        let mut index = self.cursor;
+        while index < graphemes.len() && is_space(graphemes[index]) {
+            index += 1;
+        }
+        while index < graphemes.len() && !is_space(graphemes[index]) {
+            index += 1;
+        }
+        index
+    }
+}
+
+/// Returns what to show for a grapheme, so that pasted line breaks don't
+/// break up the message bar.
+fn shown_as(grapheme: &str) -> &str {
+    if grapheme == "\n" || grapheme == "\r\n" {
+        "\u{21b5}"
+    } else {
+        grapheme
+    }
+}
+
+fn is_space(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn line(text: &str, cursor: usize) -> PromptLine {
+        let mut line = PromptLine::default();
+        line.set(text);
+        line.cursor = cursor;
+        line
+    }
+
+    #[test]
+    fn jumps_over_words() {
+        let mut line = line("one  two three", 14);
+        line.edit(key(KeyCode::Left, KeyModifiers::CONTROL));
+        assert_eq!(line.cursor, 9);
+        line.edit(key(KeyCode::Left, KeyModifiers::ALT));
+        assert_eq!(line.cursor, 5);
+        line.edit(key(KeyCode::Left, KeyModifiers::CONTROL));
+        assert_eq!(line.cursor, 0);
+        line.edit(key(KeyCode::Left, KeyModifiers::CONTROL));
+        assert_eq!(line.cursor, 0);
+
+        line.edit(key(KeyCode::Right, KeyModifiers::CONTROL));
+        assert_eq!(line.cursor, 3);
+        line.edit(key(KeyCode::Right, KeyModifiers::CONTROL));
+        assert_eq!(line.cursor, 8);
+        line.edit(key(KeyCode::Right, KeyModifiers::CONTROL));
+        line.edit(key(KeyCode::Right, KeyModifiers::CONTROL));
+        assert_eq!(line.cursor, 14);
+    }
+
+    #[test]
+    fn deletes_the_word_before_the_cursor() {
+        let mut line = line("cargo  test --all", 12);
+        line.edit(key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(line.text(), "cargo  --all");
+        assert_eq!(line.cursor, 7);
+        line.edit(key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(line.text(), "--all");
+        assert_eq!(line.cursor, 0);
+        line.edit(key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(line.text(), "--all");
+    }
+
+    #[test]
+    fn deletes_forward_by_grapheme() {
+        let mut line = line("a\u{e9}\u{301}b", 1);
+        line.edit(key(KeyCode::Delete, KeyModifiers::NONE));
+        assert_eq!(line.text(), "ab");
+        assert_eq!(line.cursor, 1);
+        line.edit(key(KeyCode::End, KeyModifiers::NONE));
+        line.edit(key(KeyCode::Delete, KeyModifiers::NONE));
+        assert_eq!(line.text(), "ab");
+        line.edit(key(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(line.text(), "a");
+    }
+
+    #[test]
+    fn scrolls_to_keep_the_cursor_in_view() {
+        let mut line = line("abcdefghij", 10);
+        // The cursor needs a cell of its own after the last character.
+        assert_eq!(line.view(4), ("hij".to_string(), 3));
+
+        line.edit(key(KeyCode::Home, KeyModifiers::NONE));
+        assert_eq!(line.view(4), ("abcd".to_string(), 0));
+
+        line.cursor = 6;
+        assert_eq!(line.view(4), ("defg".to_string(), 3));
+
+        // Moving back within the view doesn't scroll.
+        line.cursor = 4;
+        assert_eq!(line.view(4), ("defg".to_string(), 1));
+    }
+
+    #[test]
+    fn counts_wide_characters_as_two_cells() {
+        let mut line = line("\u{4e16}\u{754c}ab", 2);
+        assert_eq!(line.view(10), ("\u{4e16}\u{754c}ab".to_string(), 4));
+        line.cursor = 4;
+        assert_eq!(line.view(4), ("ab".to_string(), 2));
+    }
+}
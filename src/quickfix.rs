@@ -0,0 +1,281 @@
+use std::fs;
+use std::io::{self, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::OnceLock;
+use std::thread;
+
+use regex::Regex;
+
+/// A location found in the output of a compiler or linter.
+pub struct Entry {
+    pub file: String,
+    /// The row and column of the location, both counted from zero, as they
+    /// were when the output was read.
+    pub y: usize,
+    pub x: usize,
+    pub message: String,
+    /// Which mark of the buffer of `file` follows the location as the
+    /// buffer is edited, once the file is open.
+    pub mark: Option<usize>,
+}
+
+/// The locations from the last build, and which of them was gone to last.
+#[derive(Default)]
+pub struct Quickfix {
+    pub entries: Vec<Entry>,
+    pub current: Option<usize>,
+}
+
+impl Quickfix {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Self {
+            entries,
+            current: None,
+        }
+    }
+
+    /// Returns the entry after the current one, or before it if `forward`
+    /// is false, or None at the end of the list.
+    pub fn step(&self, forward: bool) -> Option<usize> {
+        match (self.current, forward) {
+            (None, _) if self.entries.is_empty() => None,
+            (None, true) => Some(0),
+            (None, false) => Some(self.entries.len() - 1),
+            (Some(current), true) => Some(current + 1).filter(|next| *next < self.entries.len()),
+            (Some(current), false) => current.checked_sub(1),
+        }
+    }
+}
+
+/// A build command running in the background.
+pub struct Build {
+    pub command: String,
+    receiver: Receiver<Result<String, io::Error>>,
+}
+
+impl Build {
+    pub fn start(command: &str) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let owned = command.to_string();
+        thread::spawn(move || {
+            // The editor is gone if the output can't be sent.
+            let _ = sender.send(run(&owned));
+        });
+
+        Self {
+            command: command.to_string(),
+            receiver,
+        }
+    }
+
+    /// Returns what the command printed, once it is done.
+    pub fn output(&self) -> Option<Result<String, io::Error>> {
+        match self.receiver.try_recv() {
+            Ok(output) => Some(output),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(io::Error::other("the build stopped without output")))
+            }
+        }
+    }
+}
+
+/// Runs `command` with the shell and returns what it printed, its errors
+/// after its output.
+fn run(command: &str) -> Result<String, io::Error> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::null())
+        .output()?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(text)
+}
+
+/// Reads compiler output from the file at `path`, or from the standard
+/// input if `path` is `-`.
+pub fn read(path: &str) -> Result<String, io::Error> {
+    if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+/// Finds the locations in compiler output: lines like
+/// `file:line:column: message` or `file:line: message`, as gcc and many
+/// linters print them, and rustc's `error: message` followed by a
+/// `--> file:line:column` line.
+pub fn parse(output: &str) -> Vec<Entry> {
+    static LOCATION: OnceLock<Regex> = OnceLock::new();
+    let location = LOCATION.get_or_init(|| {
+        Regex::new(r"^((?:[A-Za-z]:)?[^:\s][^:]*):(\d+)(?::(\d+))?(?::\s*(.*))?$")
+            .expect("the location pattern is valid")
+    });
+
+    let mut entries = Vec::new();
+    // The last rustc error or warning, waiting for its location.
+    let mut heading: Option<&str> = None;
+    for line in output.lines() {
+        let line = line.trim_end();
+        let trimmed = line.trim_start();
+
+        if let Some(arrow) = trimmed.strip_prefix("--> ") {
+            if let (Some(message), Some(captures)) = (heading.take(), location.captures(arrow)) {
+                entries.push(entry(&captures, message));
+            }
+        } else if let Some(captures) = location
+            .captures(line)
+            .filter(|captures| captures.get(4).is_some())
+        {
+            let message = captures.get(4).map_or("", |message| message.as_str());
+            entries.push(entry(&captures, message));
+            heading = None;
+        } else if line.starts_with("error") || line.starts_with("warning") {
+            heading = Some(line);
+        }
+    }
+    entries
+}
+
+fn entry(captures: &regex::Captures, message: &str) -> Entry {
+    let number = |group: usize| {
+        captures
+            .get(group)
+            .and_then(|number| number.as_str().parse::<usize>().ok())
+            .unwrap_or(1)
+    };
+    Entry {
+        file: captures[1].to_string(),
+        y: number(2).saturating_sub(1),
+        x: number(3).saturating_sub(1),
+        message: message.to_string(),
+        mark: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_locations(output: &str, expected: &[(&str, usize, usize, &str)]) {
+        let entries = parse(output);
+        let found: Vec<_> = entries
+            .iter()
+            .map(|entry| (&entry.file[..], entry.y, entry.x, &entry.message[..]))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn parses_rustc_errors_and_warnings() {
+        let output = "\
+   Compiling demo v0.1.0 (/tmp/demo)
+error[E0308]: mismatched types
+ --> src/main.rs:4:18
+  |
+4 |     let x: u32 = \"a\";
+  |            ---   ^^^ expected `u32`, found `&str`
+  |
+warning: unused variable: `y`
+  --> src/lib.rs:10:9
+   |
+note: this note has no location of its own
+error: could not compile `demo` (bin \"demo\") due to 1 previous error
+";
+        assert_locations(
+            output,
+            &[
+                ("src/main.rs", 3, 17, "error[E0308]: mismatched types"),
+                ("src/lib.rs", 9, 8, "warning: unused variable: `y`"),
+            ],
+        );
+    }
+
+    #[test]
+    fn parses_gcc_style_locations() {
+        let output = "\
+main.c: In function 'main':
+main.c:3:5: error: 'x' undeclared (first use in this function)
+In file included from lib.h:2,
+lib.c:7: warning: no column here
+";
+        assert_locations(
+            output,
+            &[
+                (
+                    "main.c",
+                    2,
+                    4,
+                    "error: 'x' undeclared (first use in this function)",
+                ),
+                ("lib.c", 6, 0, "warning: no column here"),
+            ],
+        );
+    }
+
+    #[test]
+    fn parses_grep_output() {
+        let output = "\
+src/a.rs:12:    let total = 42;
+src/b.rs:3:42 is the answer
+Binary file target/app matches
+";
+        assert_locations(
+            output,
+            &[
+                ("src/a.rs", 11, 0, "let total = 42;"),
+                ("src/b.rs", 2, 0, "42 is the answer"),
+            ],
+        );
+    }
+
+    #[test]
+    fn allows_spaces_and_drive_letters_in_paths() {
+        let output = "\
+my project/src/main.c:1:2: error: bad
+C:\\work\\main.c:5:1: warning: odd
+error: broken
+ --> my crate/src/lib.rs:8:3
+";
+        assert_locations(
+            output,
+            &[
+                ("my project/src/main.c", 0, 1, "error: bad"),
+                ("C:\\work\\main.c", 4, 0, "warning: odd"),
+                ("my crate/src/lib.rs", 7, 2, "error: broken"),
+            ],
+        );
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_locations() {
+        let output = "\
+warning: unused import: `std::fs`
+test result: ok. 3 passed; 0 failed
+    Finished `dev` profile
+";
+        assert!(parse(output).is_empty());
+    }
+
+    #[test]
+    fn runs_the_build_in_the_background() {
+        let build = Build::start("echo 'a.rs:1:2: error: out' && echo 'b.rs:3: warning: err' >&2");
+        let output = loop {
+            if let Some(output) = build.output() {
+                break output.unwrap();
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(output, "a.rs:1:2: error: out\nb.rs:3: warning: err\n");
+        assert_eq!(parse(&output).len(), 2);
+    }
+}
@@ -3,14 +3,14 @@ use std::io::stdout;
 
 use crate::Terminal;
 
-use crate::SearchDirection;
-
 use crossterm::{
     queue,
     style::{Attribute, SetAttribute},
 };
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+#[derive(Default)]
 pub struct Row {
     string: String,
     highlighted: String,
@@ -18,14 +18,6 @@ pub struct Row {
 }
 
 impl Row {
-    pub fn default() -> Self {
-        Self {
-            string: String::new(),
-            highlighted: String::new(),
-            len: 0,
-        }
-    }
-
     pub fn from(st: &str, highlighted: &str) -> Self {
         Self {
             string: String::from(st),
@@ -34,73 +26,71 @@ impl Row {
         }
     }
 
-    pub fn render(&self, start: usize, end: usize, search_keyword: &Option<String>) {
+    /// Prints the graphemes from `start` to `end`, showing the `reversed`
+    /// ranges in reverse video and the `underlined` ones underlined.
+    ///
+    /// A reversed range that reaches past the end of the row shows as a
+    /// reversed blank after it, like a cursor at the end of the row.
+    pub fn render(
+        &self,
+        start: usize,
+        end: usize,
+        reversed: &[(usize, usize)],
+        underlined: &[(usize, usize)],
+    ) {
         let mut prev_esc_seq = String::new();
 
-        let reverse_colors_start: usize;
-        let reverse_colors_end: usize;
-
-        if let Some(st) = search_keyword {
-            if let Some(pos) = self.find(&st[..], 0, SearchDirection::Forward) {
-                reverse_colors_start = pos.saturating_sub(start);
-                reverse_colors_end = pos.saturating_add(st.len()).saturating_sub(start);
-            }
-            else {
-                reverse_colors_start = 0;
-                reverse_colors_end = 0;
-            }
-        }
-        else {
-            reverse_colors_start = 0;
-            reverse_colors_end = 0;
-        }
+        let covers = |ranges: &[(usize, usize)], index: usize| {
+            ranges
+                .iter()
+                .any(|(from, to)| (*from..*to).contains(&index))
+        };
+        let past_end = (start..end).contains(&self.len) && covers(reversed, self.len);
 
         let end = cmp::min(end, self.len);
         let start = cmp::min(start, end);
 
         let mut flag = false;
+        let mut style = (false, false);
 
         let mut skip = 0;
         let mut chars = 0;
 
-        let mut stdout = stdout();
-
         for grapheme in self.highlighted[..].graphemes(true) {
             if grapheme == "\x1B" {
                 flag = true;
             }
-            if flag == true && (grapheme == "m") {
+            if flag && (grapheme == "m") {
                 flag = false;
                 prev_esc_seq.push_str(grapheme);
                 print!("{}", grapheme);
                 continue;
             }
 
-            if flag == false {
+            if !flag {
                 if skip == start {
                     if chars < end - start {
-                        if reverse_colors_start + reverse_colors_end != 0 {
-                            if chars == reverse_colors_start {
-                                if queue!(stdout, SetAttribute(Attribute::Reverse)).is_err() {
-                                    Terminal::cleanup_and_exit(Some("Error: Couldn't write to stdout"), 101);
-                                };
+                        let index = start + chars;
+                        let next_style = (covers(reversed, index), covers(underlined, index));
+
+                        if next_style != style {
+                            set_attribute(Attribute::Reset);
+                            print!("{}", prev_esc_seq);
+                            if next_style.0 {
+                                set_attribute(Attribute::Reverse);
                             }
+                            if next_style.1 {
+                                set_attribute(Attribute::Underlined);
+                            }
+                            style = next_style;
                         }
+
                         if grapheme == "\t" {
                             print!(" ");
                         } else {
                             print!("{}", grapheme);
                         }
                         chars += 1;
-
-                        if reverse_colors_start + reverse_colors_end != 0 {
-                            if chars == reverse_colors_end {
-                                if queue!(stdout, SetAttribute(Attribute::Reset)).is_err() {
-                                    Terminal::cleanup_and_exit(Some("Error: Couldn't write to stdout"), 101);
-                                };
-                            }
-                            print!("{}", prev_esc_seq);
-                        }
                     } else {
                         break;
                     }
@@ -111,146 +101,132 @@ impl Row {
                 prev_esc_seq.push_str(grapheme);
                 print!("{}", grapheme);
             }
-
         }
-        if queue!(stdout, SetAttribute(Attribute::Reset)).is_err() {
-            Terminal::cleanup_and_exit(Some("Error: Couldn't write to stdout"), 101);
-        };
+        if past_end {
+            set_attribute(Attribute::Reset);
+            print!("{}", prev_esc_seq);
+            set_attribute(Attribute::Reverse);
+            print!(" ");
+        }
+        set_attribute(Attribute::Reset);
 
         print!("{}", prev_esc_seq);
-        print!("\r\n");
     }
 
-    pub fn len(&self) -> usize {
-        self.len
+    /// Returns the number of terminal cells `text` takes up.
+    pub fn width(text: &str) -> usize {
+        text.graphemes(true).map(Self::grapheme_width).sum()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.len == 0
+    /// Joins as many of `graphemes` as fit in `width` cells.
+    pub fn fit<'a>(graphemes: impl IntoIterator<Item = &'a str>, width: usize) -> String {
+        let mut fitted = String::new();
+        let mut used = 0;
+        for grapheme in graphemes {
+            used += Self::grapheme_width(grapheme);
+            if used > width {
+                break;
+            }
+            fitted.push_str(grapheme);
+        }
+        fitted
     }
 
-    pub fn get_string(&self) -> &str {
-        &self.string[..]
+    /// Returns the number of terminal cells `render` uses for a grapheme.
+    pub fn grapheme_width(grapheme: &str) -> usize {
+        if grapheme == "\t" {
+            1
+        } else {
+            grapheme.width()
+        }
     }
 
-    pub fn get_highlighted(&self) -> &str {
-        &self.highlighted[..]
+    /// Returns the display cell at which the grapheme at `at` starts. Past
+    /// the end of the row, every grapheme counts as one cell.
+    pub fn cell_of(&self, at: usize) -> usize {
+        let cells: usize = self.string[..]
+            .graphemes(true)
+            .take(at)
+            .map(Self::grapheme_width)
+            .sum();
+        cells + at.saturating_sub(self.len)
     }
 
-    pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len {
-            self.string.push(c);
-            self.len += 1;
-            return;
-        }
-        let mut result = String::new();
-        let mut length = 0;
-
+    /// Returns the index of the grapheme drawn at display cell `cell`, or of
+    /// the first grapheme after it if `cell` falls inside a wide grapheme.
+    /// Past the end of the row, every grapheme counts as one cell.
+    pub fn index_at_cell(&self, cell: usize) -> usize {
+        let mut cells = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            length += 1;
-            if index == at {
-                length += 1;
-                result.push(c);
+            if cells >= cell {
+                return index;
             }
-            result.push_str(grapheme);
+            cells += Self::grapheme_width(grapheme);
         }
+        self.len + cell.saturating_sub(cells)
+    }
 
-        self.len = length;
-        self.string = result;
+    /// Returns the graphemes from `start` up to `end`.
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect()
     }
 
-    pub fn delete(&mut self, at: usize) {
-        if at >= self.len {
-            return;
-        }
+    /// Returns the range of graphemes of the word under or right before `at`.
+    pub fn word_at(&self, at: usize) -> Option<(usize, usize)> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let is_word = |index: usize| {
+            graphemes
+                .get(index)
+                .is_some_and(|grapheme| grapheme.chars().all(|c| c.is_alphanumeric() || c == '_'))
+        };
 
-        let mut result = String::new();
-        let mut length = 0;
+        let at = if is_word(at) {
+            at
+        } else if at > 0 && is_word(at - 1) {
+            at - 1
+        } else {
+            return None;
+        };
 
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index != at {
-                length += 1;
-                result.push_str(grapheme);
-            }
+        let mut start = at;
+        while start > 0 && is_word(start - 1) {
+            start -= 1;
+        }
+        let mut end = at + 1;
+        while is_word(end) {
+            end += 1;
         }
 
-        self.len = length;
-        self.string = result;
+        Some((start, end))
     }
 
-    pub fn append(&mut self, next_row: &Self) {
-        self.string = format!("{}{}", self.string, next_row.string);
-        self.len += next_row.len;
+    pub fn len(&self) -> usize {
+        self.len
     }
 
-    pub fn split(&mut self, at: usize) -> Self {
-        let mut row = String::new();
-        let mut length = 0;
-        let mut splitted_row = String::new();
-        let mut splitted_length = 0;
-
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                row.push_str(grapheme);
-            } else {
-                splitted_length += 1;
-                splitted_row.push_str(grapheme);
-            }
-        }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 
-        self.string = row;
-        self.len = length;
+    pub fn get_string(&self) -> &str {
+        &self.string[..]
+    }
 
-        Self {
-            string: splitted_row,
-            highlighted: self.highlighted.clone(),
-            len: splitted_length,
-        }
+    pub fn get_highlighted(&self) -> &str {
+        &self.highlighted[..]
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
+}
 
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
-        if at > self.len {
-            return None;
-        }
-
-        let start = if direction == SearchDirection::Forward {
-            at
-        } else {
-            0
-        };
-
-        let end = if direction == SearchDirection::Forward {
-            self.len
-        } else {
-            at
-        };
-
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
-        } else {
-            substring.rfind(query)
-        };
-
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
-            {
-                if matching_byte_index == byte_index {
-                    return Some(start + grapheme_index);
-                }
-            }
-        }
-
-        None
+fn set_attribute(attribute: Attribute) {
+    if queue!(stdout(), SetAttribute(attribute)).is_err() {
+        Terminal::cleanup_and_exit(Some("Error: Couldn't write to stdout"), 101);
     }
 }
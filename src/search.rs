@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+/// How the text typed into a search prompt is matched.
+#[derive(Default, Clone, Copy)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub ignore_case: bool,
+    /// Ignores case unless the query contains an uppercase letter.
+    pub smart_case: bool,
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    /// Describes the options that are switched on, for the message bar.
+    pub fn label(self) -> String {
+        [
+            (self.regex, "[regex]"),
+            (self.ignore_case, "[ignore case]"),
+            (self.smart_case, "[smart case]"),
+            (self.whole_word, "[whole word]"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, label)| *label)
+        .collect::<Vec<&str>>()
+        .join(" ")
+    }
+
+    fn ignores_case(self, text: &str) -> bool {
+        self.ignore_case || (self.smart_case && !text.chars().any(char::is_uppercase))
+    }
+}
+
+/// A compiled search, matching either a literal string or a regex.
+///
+/// Regexes are matched in multi-line mode, so `^` and `$` match at the
+/// start and end of every row.
+#[derive(Clone)]
+pub struct Query {
+    regex: Regex,
+    is_regex: bool,
+    ignore_case: bool,
+    whole_word: bool,
+}
+
+impl PartialEq for Query {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+            && self.is_regex == other.is_regex
+            && self.ignore_case == other.ignore_case
+            && self.whole_word == other.whole_word
+    }
+}
+
+impl Query {
+    pub fn new(text: &str, options: SearchOptions) -> Result<Self, regex::Error> {
+        let pattern = if options.regex {
+            text.to_string()
+        } else {
+            regex::escape(text)
+        };
+
+        let ignore_case = options.ignores_case(text);
+        Ok(Self {
+            regex: RegexBuilder::new(&pattern)
+                .multi_line(true)
+                .case_insensitive(ignore_case)
+                .build()?,
+            is_regex: options.regex,
+            ignore_case,
+            whole_word: options.whole_word,
+        })
+    }
+
+    /// Returns the byte range of the first match in `haystack` that starts
+    /// at or after byte `at`. Empty matches are skipped, since there is
+    /// nothing to show or replace.
+    pub fn find_after(&self, haystack: &str, at: usize) -> Option<Range<usize>> {
+        let mut start = at;
+        while start <= haystack.len() {
+            let found = self.regex.find_at(haystack, start)?;
+            if self.accepts(haystack, &found.range()) {
+                return Some(found.range());
+            }
+            start = next_boundary(haystack, found.start());
+        }
+        None
+    }
+
+    /// Returns the byte ranges of the matches in `haystack` that start in
+    /// `within`.
+    pub fn find_all(&self, haystack: &str, within: Range<usize>) -> Vec<Range<usize>> {
+        let mut found = Vec::new();
+        let mut at = within.start;
+        while let Some(range) = self.find_after(haystack, at) {
+            if range.start >= within.end {
+                break;
+            }
+            at = range.end;
+            found.push(range);
+        }
+        found
+    }
+
+    /// Returns the byte range of the last non-empty match in `haystack`
+    /// that starts before byte `before`.
+    pub fn find_before(&self, haystack: &str, before: usize) -> Option<Range<usize>> {
+        self.regex
+            .find_iter(haystack)
+            .take_while(|found| found.start() < before)
+            .filter(|found| self.accepts(haystack, &found.range()))
+            .last()
+            .map(|found| found.range())
+    }
+
+    /// Returns the text that replaces the match at `range` in `haystack`.
+    ///
+    /// For a regex, `$1`, `${name}` and so on in `template` are replaced by
+    /// the groups captured by the match. A literal template is used as is.
+    pub fn replacement(&self, haystack: &str, range: Range<usize>, template: &str) -> String {
+        if !self.is_regex {
+            return template.to_string();
+        }
+
+        let mut replacement = String::new();
+        if let Some(captures) = self
+            .regex
+            .captures_at(haystack, range.start)
+            .filter(|captures| captures.get(0).is_some_and(|found| found.range() == range))
+        {
+            captures.expand(template, &mut replacement);
+        }
+        replacement
+    }
+
+    fn accepts(&self, haystack: &str, range: &Range<usize>) -> bool {
+        !range.is_empty() && (!self.whole_word || is_whole_word(haystack, range))
+    }
+}
+
+/// Returns true if the text at `range` neither starts nor ends in the
+/// middle of a word.
+fn is_whole_word(haystack: &str, range: &Range<usize>) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    let before = haystack[..range.start].chars().next_back();
+    let first = haystack[range.clone()].chars().next();
+    let last = haystack[range.clone()].chars().next_back();
+    let after = haystack[range.end..].chars().next();
+
+    let starts = !(before.is_some_and(is_word) && first.is_some_and(is_word));
+    let ends = !(last.is_some_and(is_word) && after.is_some_and(is_word));
+    starts && ends
+}
+
+/// Returns the byte index of the char boundary after `index`.
+fn next_boundary(text: &str, index: usize) -> usize {
+    text[index..]
+        .chars()
+        .next()
+        .map_or(index + 1, |c| index + c.len_utf8())
+}
@@ -0,0 +1,50 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+const APP_NAME: &str = "lekh";
+
+/// Returns the directory where lekh keeps data that can be thrown away at
+/// any time.
+pub fn cache_dir() -> Option<PathBuf> {
+    app_dir("XDG_CACHE_HOME", "LOCALAPPDATA", ".cache")
+}
+
+/// Returns the directory where lekh looks for its config file.
+pub fn config_dir() -> Option<PathBuf> {
+    app_dir("XDG_CONFIG_HOME", "APPDATA", ".config")
+}
+
+/// Returns the directory where lekh keeps state that should survive
+/// between sessions, such as prompt history.
+pub fn state_dir() -> Option<PathBuf> {
+    app_dir("XDG_STATE_HOME", "LOCALAPPDATA", ".local/state")
+}
+
+/// Returns lekh's directory in the XDG base directory named by `xdg_var`,
+/// or in the one named by `windows_var` or below `home` when it isn't set.
+fn app_dir(xdg_var: &str, windows_var: &str, home: &str) -> Option<PathBuf> {
+    let base = if let Some(dir) = env::var_os(xdg_var).filter(|dir| !dir.is_empty()) {
+        PathBuf::from(dir)
+    } else if cfg!(windows) {
+        PathBuf::from(env::var_os(windows_var)?)
+    } else {
+        Path::new(&env::var_os("HOME")?).join(home)
+    };
+
+    Some(base.join(APP_NAME))
+}
+
+/// Hashes a sequence of byte chunks with 64-bit FNV-1a.
+///
+/// Unlike the hashers in `std`, the result is stable across runs and
+/// compiler versions, so it can be written to disk.
+pub fn hash<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for chunk in chunks {
+        for byte in chunk {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
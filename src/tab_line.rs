@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Document;
+use crate::Row;
+
+/// The tab of a buffer on the tab line.
+pub struct Tab {
+    pub buffer: usize,
+    pub label: String,
+    /// The cell the tab starts at.
+    pub start: usize,
+    pub width: usize,
+}
+
+/// Returns what the tab of `document` says: the last part of its file name,
+/// marked if it has unsaved changes.
+pub fn label(document: &Document) -> String {
+    let file_name = document.get_file_name();
+    let name = file_name
+        .as_deref()
+        .map(|file_name| {
+            Path::new(file_name)
+                .file_name()
+                .map_or(file_name.into(), |name| name.to_string_lossy())
+        })
+        .unwrap_or("[No Name]".into());
+    let modified = if document.is_dirty() { "*" } else { "" };
+    format!(" {name}{modified} ")
+}
+
+/// Lays out tabs with `labels` on a line `width` cells wide. When they don't
+/// all fit, the ones around the `current` tab are kept, with room for a `<`
+/// in the first cell and a `>` in the last one to show that there are more.
+/// No tabs are shown if the line is too narrow for the current one.
+pub fn arrange(labels: &[String], current: usize, width: usize) -> Vec<Tab> {
+    let max_width = width.saturating_sub(2).max(1);
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|label| truncate(label, max_width))
+        .collect();
+    let widths: Vec<usize> = labels.iter().map(|label| Row::width(label)).collect();
+
+    let (first, last, mut start) = if widths.iter().sum::<usize>() <= width {
+        (0, labels.len().saturating_sub(1), 0)
+    } else {
+        // Grow the run of tabs around the current one while it fits,
+        // taking a tab from the right and then one from the left.
+        let room = width.saturating_sub(2);
+        let Some(&used) = widths.get(current).filter(|&&used| used <= room) else {
+            return Vec::new();
+        };
+        let (mut first, mut last, mut used) = (current, current, used);
+        loop {
+            let mut grown = false;
+            if last + 1 < widths.len() && used + widths[last + 1] <= room {
+                last += 1;
+                used += widths[last];
+                grown = true;
+            }
+            if first > 0 && used + widths[first - 1] <= room {
+                first -= 1;
+                used += widths[first];
+                grown = true;
+            }
+            if !grown {
+                break;
+            }
+        }
+        (first, last, 1)
+    };
+
+    let mut tabs = Vec::new();
+    for (buffer, label) in labels.into_iter().enumerate().take(last + 1).skip(first) {
+        let width = widths[buffer];
+        tabs.push(Tab {
+            buffer,
+            label,
+            start,
+            width,
+        });
+        start += width;
+    }
+    tabs
+}
+
+/// Cuts `text` down to `width` cells, ending it with an ellipsis if
+/// anything had to go.
+fn truncate(text: &str, width: usize) -> String {
+    if Row::width(text) <= width {
+        return text.to_string();
+    }
+    let mut truncated = Row::fit(text.graphemes(true), width.saturating_sub(1));
+    truncated.push('\u{2026}');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(count: usize) -> Vec<String> {
+        (1..=count).map(|n| format!(" file{n} ")).collect()
+    }
+
+    /// The buffer, start and width of every tab.
+    fn layout(tabs: &[Tab]) -> Vec<(usize, usize, usize)> {
+        tabs.iter()
+            .map(|tab| (tab.buffer, tab.start, tab.width))
+            .collect()
+    }
+
+    #[test]
+    fn lays_out_tabs_that_fit_from_the_start() {
+        let tabs = arrange(&labels(3), 1, 21);
+        assert_eq!(layout(&tabs), [(0, 0, 7), (1, 7, 7), (2, 14, 7)]);
+        assert_eq!(tabs[2].label, " file3 ");
+    }
+
+    #[test]
+    fn keeps_the_current_tab_in_view_when_they_overflow() {
+        // 18 cells between the markers hold two tabs of 7.
+        assert_eq!(layout(&arrange(&labels(5), 0, 20)), [(0, 1, 7), (1, 8, 7)]);
+        assert_eq!(layout(&arrange(&labels(5), 2, 20)), [(2, 1, 7), (3, 8, 7)]);
+        assert_eq!(layout(&arrange(&labels(5), 4, 20)), [(3, 1, 7), (4, 8, 7)]);
+    }
+
+    #[test]
+    fn truncates_labels_wider_than_the_line() {
+        let labels = vec![" a_very_long_name.rs ".to_string(), " b ".to_string()];
+        let tabs = arrange(&labels, 0, 10);
+        assert_eq!(layout(&tabs), [(0, 1, 8)]);
+        assert_eq!(tabs[0].label, " a_very\u{2026}");
+    }
+
+    #[test]
+    fn shows_no_tabs_on_a_line_too_narrow_for_them() {
+        assert!(arrange(&labels(3), 1, 2).is_empty());
+        assert!(arrange(&labels(3), 1, 1).is_empty());
+        assert!(arrange(&labels(1), 0, 0).is_empty());
+
+        // A single tab shrinks to an ellipsis, which needs no markers.
+        let tabs = arrange(&labels(1), 0, 2);
+        assert_eq!(layout(&tabs), [(0, 0, 1)]);
+        assert_eq!(tabs[0].label, "\u{2026}");
+    }
+}
@@ -1,10 +1,11 @@
 use std::{
     io::{stdout, Stdout, Write},
     process,
+    time::{Duration, Instant},
 };
 
 use crossterm::{
-    event::{read, Event, KeyEvent},
+    event::{poll, read, Event, KeyEvent},
     style::Color,
     terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled},
 };
@@ -18,11 +19,12 @@ pub struct Size {
 
 pub struct Terminal {
     size: Size,
+    tab_line: bool,
     _stdout: Stdout,
 }
 
-impl Terminal {
-    pub fn default() -> Self {
+impl Default for Terminal {
+    fn default() -> Self {
         let (columns, rows) = if let Ok(size) = crossterm::terminal::size() {
             size
         } else {
@@ -37,10 +39,13 @@ impl Terminal {
 
         Terminal {
             size,
+            tab_line: false,
             _stdout: stdout(),
         }
     }
+}
 
+impl Terminal {
     pub fn get_size(&self) -> &Size {
         &self.size
     }
@@ -48,10 +53,36 @@ impl Terminal {
     pub fn set_size(&mut self, width: u16, height: u16) {
         self.size = Size {
             width,
-            height: height.saturating_sub(2),
+            height: height.saturating_sub(self.bar_rows()),
         };
     }
 
+    pub fn has_tab_line(&self) -> bool {
+        self.tab_line
+    }
+
+    /// Makes room for a tab line above the panes, on top of the status bar
+    /// and the message bar, or gives the room back.
+    pub fn set_tab_line(&mut self, shown: bool) {
+        let rows = self.size.height + self.bar_rows();
+        self.tab_line = shown;
+        self.size.height = rows.saturating_sub(self.bar_rows());
+    }
+
+    /// Returns how many rows the bars around the panes take.
+    fn bar_rows(&self) -> u16 {
+        if self.tab_line {
+            3
+        } else {
+            2
+        }
+    }
+
+    pub fn enable_mouse() -> Result<(), std::io::Error> {
+        crossterm::execute!(stdout(), crossterm::event::EnableMouseCapture)?;
+        Ok(())
+    }
+
     pub fn into_raw_mode() {
         match is_raw_mode_enabled() {
             Ok(enabled) => {
@@ -59,7 +90,9 @@ impl Terminal {
                     Terminal::cleanup_and_exit(Some("Error: Couldn't enable raw mode."), 101);
                 }
             }
-            Err(_) => Terminal::cleanup_and_exit(Some("Error: Couldn't fetch terminal state."), 101),
+            Err(_) => {
+                Terminal::cleanup_and_exit(Some("Error: Couldn't fetch terminal state."), 101)
+            }
         }
     }
 
@@ -79,6 +112,15 @@ impl Terminal {
         Ok(())
     }
 
+    /// Clears from the cursor to the end of its line.
+    pub fn clear_until_newline(&mut self) -> Result<(), std::io::Error> {
+        crossterm::execute!(
+            self._stdout,
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine),
+        )?;
+        Ok(())
+    }
+
     pub fn move_cursor(&mut self, position: &Position) -> Result<(), std::io::Error> {
         let Position { x, y } = position;
         let x = *x as u16;
@@ -98,6 +140,14 @@ impl Terminal {
         Ok(())
     }
 
+    /// Writes `text` to the terminal as is, e.g. to send escape sequences
+    /// crossterm has no command for.
+    pub fn write_raw(&mut self, text: &str) -> Result<(), std::io::Error> {
+        self._stdout.write_all(text.as_bytes())?;
+        self._stdout.flush()?;
+        Ok(())
+    }
+
     pub fn flush(&mut self) -> Result<(), std::io::Error> {
         self._stdout.flush()?;
         Ok(())
@@ -108,9 +158,46 @@ impl Terminal {
         C: FnMut(u16, u16) -> Result<(), std::io::Error>,
     {
         loop {
+            if let Event::Key(key_event) = Self::read_event(&mut resize_handler)? {
+                return Ok(key_event);
+            }
+        }
+    }
+
+    /// Like `read_key`, but returns mouse events as well as keys.
+    pub fn read_event<C>(mut resize_handler: C) -> Result<Event, std::io::Error>
+    where
+        C: FnMut(u16, u16) -> Result<(), std::io::Error>,
+    {
+        loop {
+            let event = read()?;
+            match event {
+                Event::Key(_) | Event::Mouse(_) => return Ok(event),
+                Event::Resize(width, height) => {
+                    resize_handler(width, height)?;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Like `read_event`, but returns None if no key or mouse event comes
+    /// within `timeout`.
+    pub fn poll_event<C>(
+        timeout: Duration,
+        mut resize_handler: C,
+    ) -> Result<Option<Event>, std::io::Error>
+    where
+        C: FnMut(u16, u16) -> Result<(), std::io::Error>,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !poll(deadline.saturating_duration_since(Instant::now()))? {
+                return Ok(None);
+            }
             let event = read()?;
             match event {
-                Event::Key(key_event) => return Ok(key_event),
+                Event::Key(_) | Event::Mouse(_) => return Ok(Some(event)),
                 Event::Resize(width, height) => {
                     resize_handler(width, height)?;
                 }
@@ -145,6 +232,9 @@ impl Terminal {
     }
 
     pub fn cleanup_and_exit(err: Option<&str>, mut exit_code: i32) -> ! {
+        // Harmless if mouse capture was never turned on.
+        let _ = crossterm::execute!(stdout(), crossterm::event::DisableMouseCapture);
+
         if Terminal::leave_alternate_screen().is_err() {
             eprintln!("Error: Couldn't leave alternate screen.\r");
             if exit_code == 0 {
@@ -172,9 +262,9 @@ impl Terminal {
                 if exit_code == 0 {
                     exit_code = 101;
                 }
-            },
+            }
         }
-        
+
         process::exit(exit_code);
     }
 }
@@ -0,0 +1,47 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ignore::{self, Gitignore};
+
+/// Calls `found` with every file that isn't ignored, until it returns false.
+pub fn walk<F>(mut found: F)
+where
+    F: FnMut(PathBuf) -> bool,
+{
+    let mut pending = vec![(PathBuf::new(), Gitignore::default())];
+    while let Some((dir, gitignore)) = pending.pop() {
+        let gitignore = gitignore.enter(&dir);
+        let Ok(read_dir) = fs::read_dir(ignore::listed(&dir)) else {
+            continue;
+        };
+
+        let mut entries: Vec<(PathBuf, bool)> = read_dir
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                // Symbolic links to directories aren't followed, so that a
+                // link back up the tree can't make the walk go on forever.
+                let is_dir = entry.file_type().ok()?.is_dir();
+                let name = entry.file_name();
+                if is_dir && ignore::is_vcs_directory(&name.to_string_lossy()) {
+                    return None;
+                }
+                let path = dir.join(name);
+                (!gitignore.is_ignored(&path, is_dir)).then_some((path, is_dir))
+            })
+            .collect();
+        entries.sort();
+
+        // Directories are pushed in reverse so that they come off the stack
+        // in order.
+        for (path, is_dir) in entries.iter().rev() {
+            if *is_dir {
+                pending.push((path.clone(), gitignore.clone()));
+            }
+        }
+        for (path, is_dir) in entries {
+            if !is_dir && !found(path) {
+                return;
+            }
+        }
+    }
+}